@@ -1,367 +1,2717 @@
-use std::path::PathBuf;
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use anyhow::{anyhow, bail};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as b64, Engine as _};
 use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
 use zeroize::Zeroizing;
 
 use crate::{
-    crypto::{decrypt, derive_key, encrypt, generate_salt},
+    backup::Archive,
+    crypto::{decrypt, encrypt, generate_salt, Cipher, KdfMode, KdfParams},
+    git,
     hidden::{HiddenMap, HiddenMapIndex, UnsaltedHiddenMap},
-    io::{config_dir, load_from_yaml, save_to_file},
-    login::{LoginEntry, LoginMap, SaltEntry, UnencryptedLogin},
+    import::Skipped,
+    io::{
+        load_from_yaml, lock_dir, remove_if_exists, save_to_file, stage_file, sync_parent,
+        with_suffix,
+    },
+    keys::Keys,
+    login::{
+        now, rename_legacy_fields, LoginEntry, LoginMap, PreviousPassword, SaltEntry,
+        UnencryptedLogin, RESERVED_FIELDS,
+    },
 };
 
+fn is_under(path: &str, key: &str) -> bool {
+    path == "/"
+        || key.starts_with(path) && (key.len() == path.len() || key.as_bytes()[path.len()] == b'/')
+}
+
+// an existing login that `key` would be nested in, or that would be nested in `key`
+fn collision<'a>(logins: &'a LoginMap, key: &str) -> Option<&'a String> {
+    logins
+        .logins
+        .keys()
+        .find(|p| p.as_str() != key && (is_under(p, key) || is_under(key, p)))
+}
+
+// the hidden root `key` equals, lies under or contains; unhiding would put the hidden logins on
+// top of a login there
+fn hidden_collision<'a>(hidden_logins: &'a HiddenMapIndex, key: &str) -> Option<&'a String> {
+    hidden_logins
+        .keys()
+        .find(|root| is_under(root, key) || is_under(key, root))
+}
+
+const JOURNAL_FILE: &str = "save.journal";
+// 1: implicit KDF and cipher parameters, 2: parameters recorded in every `SaltEntry`,
+// 3: batch key derivation, 4: timestamps and password history
+pub const FORMAT_VERSION: u32 = 4;
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize)]
+struct StoreInfo {
+    version: u32,
+    // used for new entries, existing entries keep the parameters in their `SaltEntry`
+    #[serde(default)]
+    kdf: KdfParams,
+    #[serde(default)]
+    kdf_mode: KdfMode,
+    // number of previous passwords kept per login
+    #[serde(default = "default_history_length")]
+    history_length: usize,
+}
+
+fn default_history_length() -> usize {
+    5
+}
+
+// stores created before the version was recorded have no store.yml
+impl Default for StoreInfo {
+    fn default() -> Self {
+        StoreInfo {
+            version: 1,
+            kdf: KdfParams::default(),
+            kdf_mode: KdfMode::default(),
+            history_length: default_history_length(),
+        }
+    }
+}
+
+// Observes bulk operations with the number of processed and total entries. Returning false
+// cancels the operation with an error, leaving the state half-modified, so it must not be saved.
+pub type Progress<'a> = &'a mut dyn FnMut(usize, usize) -> bool;
+
+fn report(progress: &mut Progress, done: usize, total: usize) -> anyhow::Result<()> {
+    if !progress(done, total) {
+        bail!("Operation canceled");
+    }
+    Ok(())
+}
+
+// a store as packed into a backup archive
+#[derive(Deserialize)]
+pub struct Backup {
+    info: StoreInfo,
+    logins: LoginMap,
+    salts: IndexMap<String, SaltEntry>,
+    hidden_logins: HiddenMapIndex,
+}
+
+impl Backup {
+    // a backup of a newer store may hold entries this version would misread or drop
+    pub fn check_version(&self) -> anyhow::Result<()> {
+        if self.info.version > FORMAT_VERSION {
+            bail!(
+                "Backup format version {} is newer than this version of qass supports ({})",
+                self.info.version,
+                FORMAT_VERSION
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct BackupRef<'a> {
+    info: StoreInfo,
+    logins: &'a LoginMap,
+    salts: &'a IndexMap<String, SaltEntry>,
+    hidden_logins: &'a HiddenMapIndex,
+}
+
+// logins and hidden paths of a backup compared to the store
+#[derive(Default, PartialEq)]
+pub struct BackupDiff {
+    pub only_in_backup: Vec<String>,
+    pub only_in_store: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+// a path changed on both sides of a merge, with what each side did relative to the base
+pub struct Conflict {
+    pub path: String,
+    pub ours: &'static str,
+    pub theirs: &'static str,
+}
+
+pub type Resolve<'a> = &'a mut dyn FnMut(&Conflict) -> anyhow::Result<Side>;
+
+#[derive(Default)]
+pub struct MergeSummary {
+    pub taken: Vec<String>,
+    pub collisions: Vec<String>,
+}
+
 pub struct State {
+    dir: PathBuf,
+    version: u32,
+    kdf: KdfParams,
+    kdf_mode: KdfMode,
+    history_length: usize,
+    // shared for read-only states, exclusive for states that may be saved, none for read-only
+    // states of stores that can't be written to
+    _lock: Option<File>,
+    exclusive: bool,
     logins: LoginMap,
     salts: IndexMap<String, SaltEntry>,
     hidden_logins: HiddenMapIndex,
 }
 
 impl State {
-    fn paths() -> anyhow::Result<(PathBuf, PathBuf, PathBuf)> {
-        let dir = config_dir()?;
+    pub fn init(dir: &Path) -> anyhow::Result<()> {
+        fs::create_dir_all(dir)?;
+
+        let (logins_path, salts_path, hidden_path, info_path) = State::paths(dir)?;
+        for path in [logins_path, salts_path, hidden_path] {
+            if !path.exists() {
+                File::create(path)?;
+            }
+        }
+        if !info_path.exists() {
+            save_to_file(
+                &info_path,
+                &StoreInfo {
+                    version: FORMAT_VERSION,
+                    ..Default::default()
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn paths(dir: &Path) -> anyhow::Result<(PathBuf, PathBuf, PathBuf, PathBuf)> {
         if !dir.exists() {
-            bail!("Config not found. Run 'qass init' first");
+            bail!(
+                "Store not found at '{}'. Run 'qass init' first",
+                dir.display()
+            );
         }
 
         let logins_path = dir.join("logins.yml");
         let salts_path = dir.join("salts.yml");
         let hidden_path = dir.join("hidden.yml");
+        let info_path = dir.join("store.yml");
+
+        Ok((logins_path, salts_path, hidden_path, info_path))
+    }
+
+    // read-only, other processes may still read the store but not save it
+    pub fn load(dir: &Path) -> anyhow::Result<State> {
+        State::open(dir, false, LOCK_TIMEOUT)
+    }
 
-        Ok((logins_path, salts_path, hidden_path))
+    pub fn load_mut(dir: &Path) -> anyhow::Result<State> {
+        State::open(dir, true, LOCK_TIMEOUT)
     }
 
-    pub fn load() -> anyhow::Result<State> {
-        let (logins_path, salts_path, hidden_path) = State::paths()?;
+    fn open(dir: &Path, exclusive: bool, timeout: Duration) -> anyhow::Result<State> {
+        let (logins_path, salts_path, hidden_path, info_path) = State::paths(dir)?;
+        let paths = [&logins_path, &salts_path, &hidden_path, &info_path];
 
-        let logins: LoginMap = load_from_yaml(&logins_path)?;
-        let salts: IndexMap<String, SaltEntry> = load_from_yaml(&salts_path)?;
+        // recovering an interrupted save writes to the store, so it always needs the exclusive lock
+        if !exclusive && State::needs_recovery(dir, &paths) {
+            let _lock = lock_dir(dir, true, timeout)?;
+            State::recover(dir, &paths)?;
+        }
+        let lock = lock_dir(dir, exclusive, timeout)?;
+        if exclusive {
+            State::recover(dir, &paths)?;
+        }
+
+        let info: StoreInfo = load_from_yaml(&info_path)?;
+        if info.version > FORMAT_VERSION {
+            bail!(
+                "Store format version {} is newer than this version of qass supports ({})",
+                info.version,
+                FORMAT_VERSION
+            );
+        }
+
+        let mut salts: IndexMap<String, SaltEntry> = load_from_yaml(&salts_path)?;
+        let logins: LoginMap = if info.version < 4 {
+            // the renamed fields are written with the next save, e.g. by `migrate`
+            let mut raw: serde_yaml::Value = load_from_yaml(&logins_path)?;
+            for (path, key, name) in rename_legacy_fields(&mut raw, "") {
+                if let Some(salt) = salts.get_mut(&path) {
+                    if let Some(field_salt) = salt.fields.shift_remove(&key) {
+                        salt.fields.insert(name, field_salt);
+                    }
+                }
+            }
+            match raw {
+                serde_yaml::Value::Null => LoginMap::new(),
+                raw => serde_yaml::from_value(raw)?,
+            }
+        } else {
+            load_from_yaml(&logins_path)?
+        };
         let hidden_logins: HiddenMapIndex = load_from_yaml(&hidden_path)?;
 
         Ok(State {
+            dir: dir.to_path_buf(),
+            version: info.version,
+            kdf: info.kdf,
+            kdf_mode: info.kdf_mode,
+            history_length: info.history_length,
+            _lock: lock,
+            exclusive,
             logins,
             salts,
             hidden_logins,
         })
     }
 
-    pub fn save(&self) -> anyhow::Result<()> {
-        let (logins_path, salts_path, hidden_path) = State::paths()?;
+    // All files are staged as `<file>.new` first. Once the journal is written the save counts as
+    // committed and the staged files are renamed into place, which `recover` finishes after a crash.
+    // In stores with git enabled, the save is then committed with `message`, which must not name
+    // hidden paths.
+    pub fn save(&self, message: &str) -> anyhow::Result<()> {
+        if !self.exclusive {
+            bail!("Store was loaded read-only and can't be saved");
+        }
 
-        save_to_file(&logins_path, &self.logins)?;
-        save_to_file(&salts_path, &self.salts)?;
-        save_to_file(&hidden_path, &self.hidden_logins)?;
+        let (logins_path, salts_path, hidden_path, info_path) = State::paths(&self.dir)?;
+        let info = StoreInfo {
+            version: self.format_version(),
+            kdf: self.kdf.clone(),
+            kdf_mode: self.kdf_mode,
+            history_length: self.history_length,
+        };
 
-        Ok(())
-    }
+        let staged = [
+            (stage_file(&logins_path, "new", &self.logins)?, logins_path),
+            (stage_file(&salts_path, "new", &self.salts)?, salts_path),
+            (
+                stage_file(&hidden_path, "new", &self.hidden_logins)?,
+                hidden_path,
+            ),
+            (stage_file(&info_path, "new", &info)?, info_path),
+        ];
 
-    pub fn add(
-        &mut self,
-        login_name: String,
-        password: Zeroizing<String>,
-        master_password: Zeroizing<String>,
-    ) -> anyhow::Result<()> {
-        self.add_many(
-            vec![UnencryptedLogin {
-                login_name,
-                password,
-                extra_fields: IndexMap::new(),
-            }],
-            master_password,
-        )
-    }
+        let journal_path = self.dir.join(JOURNAL_FILE);
+        let file_names: Vec<_> = staged
+            .iter()
+            .filter_map(|(_, p)| p.file_name()?.to_str())
+            .collect();
+        save_to_file(&journal_path, &file_names)?;
 
-    pub fn add_many(
-        &mut self,
-        logins: Vec<UnencryptedLogin>,
-        master_password: Zeroizing<String>,
-    ) -> anyhow::Result<()> {
-        for UnencryptedLogin {
-            login_name,
-            password,
-            ..
-        } in logins
-        {
-            let salt = generate_salt();
-            let key = derive_key(&master_password, &salt)?;
-            let (nonce, ciphertext) = encrypt(&password, &key)?;
-
-            self.logins.insert(
-                login_name.clone(),
-                LoginEntry {
-                    password: b64.encode(ciphertext),
-                    extra_fields: IndexMap::new(),
-                },
-            );
-            self.salts.insert(
-                login_name,
-                SaltEntry {
-                    nonce: b64.encode(nonce),
-                    salt: salt,
-                },
-            );
+        for (staged_path, path) in &staged {
+            fs::rename(staged_path, path)?;
+        }
+        sync_parent(&journal_path)?;
+
+        remove_if_exists(&journal_path)?;
+        sync_parent(&journal_path)?;
+
+        if git::is_enabled(&self.dir) {
+            git::commit(&self.dir, message)
+                .map_err(|e| anyhow!("The store was saved, but committing it failed: {}", e))?;
         }
 
         Ok(())
     }
 
-    pub fn get(
-        &self,
-        login_name: String,
-        master_password: Zeroizing<String>,
-    ) -> anyhow::Result<Zeroizing<String>> {
-        let login_entry = self
-            .logins
-            .logins
-            .get(&login_name)
-            .ok_or_else(|| anyhow!("Path '{}' not found in logins", login_name))?;
-        let salt_entry = self
+    // The store keeps its format version until it holds entries that need a newer one, so older
+    // versions of qass can still open it. Entries in hidden maps can't be inspected, but the
+    // version was already raised when they were hidden.
+    fn format_version(&self) -> u32 {
+        let logins = self.logins.logins.values().map(LoginEntry::format_version);
+        let salts = self
             .salts
-            .get(&login_name)
-            .ok_or_else(|| anyhow!("Path '{}' not found in salts", login_name))?;
-
-        let key = derive_key(&master_password, &salt_entry.salt)?;
+            .values()
+            .chain(self.hidden_logins.values().map(|h| &h.salt))
+            .map(SaltEntry::format_version);
 
-        let ciphertext = b64.decode(&login_entry.password)?;
-        let nonce = b64.decode(&salt_entry.nonce)?;
-
-        Ok(Zeroizing::new(decrypt(&ciphertext, &key, &nonce)?))
+        logins.chain(salts).fold(self.version, u32::max)
     }
 
-    pub fn hide(&mut self, path: String, master_password: Zeroizing<String>) -> anyhow::Result<()> {
-        let logins = std::mem::take(&mut self.logins);
-        let salts = std::mem::take(&mut self.salts);
-
-        let (to_hide, rest): (Vec<(_, _)>, Vec<(_, _)>) =
-            logins.logins.into_iter().partition(|(k, _)| {
-                path == "/"
-                    || k.starts_with(&path)
-                        && (k.len() == path.len() || k.chars().nth(path.len()).unwrap() == '/')
-            });
-        let (to_hide, rest): (IndexMap<_, _>, IndexMap<_, _>) =
-            (IndexMap::from_iter(to_hide), IndexMap::from_iter(rest));
+    fn needs_recovery(dir: &Path, paths: &[&PathBuf]) -> bool {
+        dir.join(JOURNAL_FILE).exists() || paths.iter().any(|p| with_suffix(p, "new").exists())
+    }
 
-        let mut salts_rest = salts;
+    // rolls a committed save forward, or discards the staged files of an uncommitted one
+    fn recover(dir: &Path, paths: &[&PathBuf]) -> anyhow::Result<()> {
+        let journal_path = dir.join(JOURNAL_FILE);
 
-        let mut hidden = UnsaltedHiddenMap::new();
-        for (k, v) in to_hide {
-            if let Some(salt) = salts_rest.shift_remove(&k) {
-                hidden.insert(k, v, salt);
-            } else {
-                bail!("No salt found for {k}.");
+        if journal_path.exists() {
+            let file_names: Vec<String> = load_from_yaml(&journal_path)?;
+            for path in file_names.iter().map(|name| dir.join(name)) {
+                let staged_path = with_suffix(&path, "new");
+                if staged_path.exists() {
+                    fs::rename(staged_path, &path)?;
+                }
+            }
+            sync_parent(&journal_path)?;
+            remove_if_exists(&journal_path)?;
+        } else {
+            for path in paths {
+                remove_if_exists(&with_suffix(path, "new"))?;
             }
         }
 
-        let hidden_str = serde_yaml::to_string(&hidden)?;
-
-        let salt = generate_salt();
-        let key = derive_key(&master_password, &salt)?;
-        let (nonce, ciphertext) = encrypt(&hidden_str, &key)?;
-
-        self.hidden_logins.insert(
-            path,
-            HiddenMap {
-                logins: b64.encode(ciphertext),
-                salt: SaltEntry {
-                    nonce: b64.encode(nonce),
-                    salt,
-                },
-            },
-        );
+        Ok(())
+    }
 
-        self.logins = LoginMap::from(rest);
-        self.salts = salts_rest;
+    fn keys<'a>(&self, master_password: &'a str) -> Keys<'a> {
+        Keys::new(master_password, self.kdf.clone(), self.kdf_mode)
+    }
 
-        Ok(())
+    pub fn version(&self) -> u32 {
+        self.version
     }
 
-    fn decrypt_hidden(
-        hidden_logins: &HiddenMapIndex,
-        path: &String,
-        master_password: &str,
-    ) -> anyhow::Result<UnsaltedHiddenMap> {
-        let hidden_map = hidden_logins
-            .get(path)
-            .ok_or_else(|| anyhow!("Path '{}' not found", path))?;
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
 
-        let key = derive_key(&master_password, &hidden_map.salt.salt)?;
-        let nonce = b64.decode(&hidden_map.salt.nonce)?;
-        let ciphertext = b64.decode(&hidden_map.logins)?;
+    pub fn kdf_mode(&self) -> KdfMode {
+        self.kdf_mode
+    }
 
-        let hidden_str = decrypt(&ciphertext, &key, &nonce)?;
-        let hidden: UnsaltedHiddenMap = serde_yaml::from_str(&hidden_str)?;
+    pub fn set_kdf_mode(&mut self, kdf_mode: KdfMode) {
+        self.kdf_mode = kdf_mode;
+    }
 
-        Ok(hidden)
+    pub fn set_kdf(&mut self, kdf: KdfParams) -> anyhow::Result<()> {
+        kdf.validate()?;
+        self.kdf = kdf;
+        Ok(())
     }
 
-    pub fn unhide(
+    // re-encrypts the entries and hidden maps under `path` in memory, each with a fresh salt and
+    // nonce, and returns how many were re-encrypted plus the ones that failed to decrypt with `old`
+    pub fn rekey(
         &mut self,
         path: String,
-        master_password: Zeroizing<String>,
-    ) -> anyhow::Result<()> {
-        let hidden = State::decrypt_hidden(&self.hidden_logins, &path, &master_password)?;
-
-        for (login_key, entry) in hidden.logins {
-            self.logins.insert(login_key.clone(), entry.login);
-            self.salts.insert(login_key, entry.salt);
-        }
-
-        self.hidden_logins.shift_remove(&path);
-
-        Ok(())
+        old_master_password: Zeroizing<String>,
+        new_master_password: Zeroizing<String>,
+        mut progress: Progress,
+    ) -> anyhow::Result<(usize, Vec<String>)> {
+        self.rekey_where(
+            &path,
+            &old_master_password,
+            &new_master_password,
+            |_| true,
+            &mut progress,
+        )
     }
 
-    pub fn get_hidden(
-        &self,
+    // like `rekey`, but keeps the master password and skips entries already using the store's KDF
+    // parameters
+    pub fn rekey_kdf(
+        &mut self,
         path: String,
-        master_password_unhide: Zeroizing<String>,
         master_password: Zeroizing<String>,
-    ) -> anyhow::Result<Zeroizing<String>> {
-        let hidden = self
+        mut progress: Progress,
+    ) -> anyhow::Result<(usize, Vec<String>)> {
+        let (kdf, kdf_mode) = (self.kdf.clone(), self.kdf_mode);
+        self.rekey_where(
+            &path,
+            &master_password,
+            &master_password,
+            |salt| !salt.uses_kdf(&kdf, kdf_mode),
+            &mut progress,
+        )
+    }
+
+    fn rekey_where(
+        &mut self,
+        path: &str,
+        old_master_password: &str,
+        new_master_password: &str,
+        filter: impl Fn(&SaltEntry) -> bool,
+        progress: &mut Progress,
+    ) -> anyhow::Result<(usize, Vec<String>)> {
+        let logins: Vec<String> = self
+            .salts
+            .iter()
+            .filter(|(p, salt)| is_under(path, p) && filter(salt))
+            .map(|(p, _)| p.clone())
+            .collect();
+
+        let roots: Vec<String> = self
             .hidden_logins
             .keys()
-            .filter(|p| path.starts_with(*p))
-            .find_map(|p| {
-                let h = State::decrypt_hidden(&self.hidden_logins, p, &master_password_unhide);
-                h.ok().and_then(|h| h.logins.get(&path).cloned())
-            })
-            .ok_or_else(|| anyhow!("Path '{}' not found in logins", path))?;
+            .filter(|root| is_under(path, root) || is_under(root, path))
+            .cloned()
+            .collect();
 
-        let key = derive_key(&master_password, &hidden.salt.salt)?;
-        let ciphertext = b64.decode(&hidden.login.password)?;
-        let nonce = b64.decode(&hidden.salt.nonce)?;
+        let mut old_keys = self.keys(old_master_password);
+        let mut new_keys = self.keys(new_master_password);
 
-        Ok(Zeroizing::new(decrypt(&ciphertext, &key, &nonce)?))
-    }
+        let logins_len = logins.len();
+        let total = logins_len + roots.len();
+        let mut count = 0;
+        let mut failed = vec![];
+        for (i, login) in logins.into_iter().enumerate() {
+            report(progress, i, total)?;
 
-    pub fn import_csv(
-        &mut self,
-        path: String,
-        master_password: Zeroizing<String>,
-    ) -> anyhow::Result<usize> {
-        let file = std::fs::File::open(&path)?;
-        let mut reader = csv::Reader::from_reader(file);
+            let (Some(entry), Some(salt)) =
+                (self.logins.logins.get(&login), self.salts.get(&login))
+            else {
+                continue;
+            };
 
-        let headers = reader.headers()?.clone();
+            let Ok(cleartext) = decrypt_login(login.clone(), entry, salt, &mut old_keys) else {
+                failed.push(login);
+                continue;
+            };
 
-        let url_idx = headers
-            .iter()
-            .position(|h| h == "url")
-            .ok_or_else(|| anyhow!("Missing 'url' column"))?;
-        let username_idx = headers
-            .iter()
-            .position(|h| h == "username")
-            .ok_or_else(|| anyhow!("Missing 'username' column"))?;
-        let password_idx = headers
-            .iter()
-            .position(|h| h == "password")
-            .ok_or_else(|| anyhow!("Missing 'password' column"))?;
+            let (login, entry, salt) = encrypt_login(cleartext, &mut new_keys)?;
+            self.logins.insert(login.clone(), entry);
+            self.salts.insert(login, salt);
+            count += 1;
+        }
 
-        let mut logins = vec![];
-        for result in reader.records() {
-            let record = result?;
+        for (i, root) in roots.into_iter().enumerate() {
+            report(progress, logins_len + i, total)?;
 
-            if record.len() <= url_idx
-                || record.len() <= username_idx
-                || record.len() <= password_idx
-            {
+            let Ok(mut hidden) = State::decrypt_hidden(&self.hidden_logins, &root, &mut old_keys)
+            else {
+                failed.push(root);
                 continue;
-            }
+            };
 
-            let url = &record[url_idx];
-            let username = &record[username_idx];
-            let password = &record[password_idx];
+            // a hidden map only gets the new master password if it lies entirely under `path`
+            let map_rekeyed = is_under(path, &root);
+            let mut changed = map_rekeyed && new_master_password != old_master_password
+                || filter(&self.hidden_logins[&root].salt);
 
-            let mut login_name = url.to_string();
-            login_name.push('/');
-            login_name.push_str(username);
+            // entries of a hidden map are reported by its root, which doesn't reveal their paths
+            let mut entries_failed = false;
+            for (login, hidden_entry) in hidden.logins.iter_mut() {
+                if !is_under(path, login) || !filter(&hidden_entry.salt) {
+                    continue;
+                }
 
-            logins.push(UnencryptedLogin {
-                login_name,
-                password: Zeroizing::new(password.to_string()),
-                extra_fields: IndexMap::new(),
-            });
+                let Ok(cleartext) = decrypt_login(
+                    login.clone(),
+                    &hidden_entry.login,
+                    &hidden_entry.salt,
+                    &mut old_keys,
+                ) else {
+                    entries_failed = true;
+                    continue;
+                };
+
+                let (_, entry, salt) = encrypt_login(cleartext, &mut new_keys)?;
+                hidden_entry.login = entry;
+                hidden_entry.salt = salt;
+                count += 1;
+                changed = true;
+            }
+            if entries_failed {
+                failed.push(root.clone());
+            }
+
+            if changed {
+                let hidden_str = Zeroizing::new(serde_yaml::to_string(&hidden)?);
+                let map_keys = if map_rekeyed {
+                    &mut new_keys
+                } else {
+                    &mut old_keys
+                };
+                let (ciphertext, salt) = encrypt_secret(&hidden_str, map_keys)?;
+                self.hidden_logins.insert(
+                    root,
+                    HiddenMap {
+                        logins: ciphertext,
+                        salt,
+                    },
+                );
+            }
         }
 
-        let count = logins.len();
-        self.add_many(logins, master_password)?;
+        report(progress, total, total)?;
 
-        Ok(count)
+        Ok((count, failed))
     }
 
-    pub fn list(&self) -> Vec<String> {
-        let logins: Vec<String> = self
-            .logins
-            .logins
-            .keys()
-            .filter(|key| self.salts.contains_key(*key))
-            .cloned()
-            .collect();
+    // records the implicit parameters of legacy entries and bumps the format version; entries in
+    // hidden maps stay implicit until unhidden, which is fine since missing parameters still mean
+    // the legacy defaults
+    pub fn migrate(&mut self) -> usize {
+        let mut count = 0;
+        for salt in self.salts.values_mut() {
+            if salt.make_explicit() {
+                count += 1;
+            }
+        }
+        for hidden in self.hidden_logins.values_mut() {
+            if hidden.salt.make_explicit() {
+                count += 1;
+            }
+        }
 
-        logins
+        self.version = FORMAT_VERSION;
+        count
     }
 
-    pub fn unlock(&mut self, path: String, master_password: Zeroizing<String>) -> usize {
-        let logins: Vec<String> = self
-            .salts
-            .keys()
-            .cloned()
-            .filter(|p| {
-                path == "/"
-                    || p.starts_with(&path)
-                        && (p.len() == path.len() || p.chars().nth(path.len()).unwrap() == '/')
-            })
-            .collect();
+    pub fn add(
+        &mut self,
+        login_name: String,
+        password: Zeroizing<String>,
+        master_password: Zeroizing<String>,
+    ) -> anyhow::Result<()> {
+        let mut keys = self.keys(&master_password);
 
-        let mut unlocked_count = 0;
+        let history_length = self.history_length;
+        if let (Some(entry), Some(salt_entry)) = (
+            self.logins.logins.get_mut(&login_name),
+            self.salts.get_mut(&login_name),
+        ) {
+            let (ciphertext, salt) = encrypt_secret(&password, &mut keys)?;
+            let mut previous_salt = std::mem::replace(salt_entry, salt);
+            salt_entry.fields = std::mem::take(&mut previous_salt.fields);
+            salt_entry.history = std::mem::take(&mut previous_salt.history);
 
-        for login in logins {
-            match self.get(login.clone(), master_password.clone()) {
-                Ok(cleartext) => {
-                    if let Some(entry) = self.logins.logins.get_mut(&login) {
-                        entry.password = cleartext.to_string();
-                        self.salts.shift_remove(&login);
-                        unlocked_count += 1;
-                    }
-                }
-                Err(_) => continue,
-            }
+            let previous = PreviousPassword {
+                password: std::mem::replace(&mut entry.password, ciphertext),
+                replaced: now(),
+            };
+            entry.password_history.insert(0, previous);
+            entry.password_history.truncate(history_length);
+            salt_entry.history.insert(0, previous_salt);
+            salt_entry.history.truncate(history_length);
+            entry.modified = Some(now());
+
+            return Ok(());
         }
 
-        unlocked_count
+        let login = match self.logins.logins.get(&login_name) {
+            Some(entry) => {
+                let mut login = UnencryptedLogin::from_cleartext(login_name, entry);
+                let previous = std::mem::replace(&mut login.password, password);
+                login.history.insert(0, (previous, now()));
+                login.history.truncate(history_length);
+                login.modified = Some(now());
+                login
+            }
+            None => UnencryptedLogin::new(login_name, password),
+        };
+
+        self.add_many(vec![login], master_password, &mut |_, _| true)
     }
 
-    pub fn sync(
+    pub fn add_many(
         &mut self,
-        path: String,
+        logins: Vec<UnencryptedLogin>,
         master_password: Zeroizing<String>,
-    ) -> anyhow::Result<usize> {
-        self.salts = std::mem::take(&mut self.salts)
-            .into_iter()
-            .filter(|(p, _)| self.logins.logins.contains_key(p))
-            .collect();
+        mut progress: Progress,
+    ) -> anyhow::Result<()> {
+        let mut keys = self.keys(&master_password);
 
-        let to_add: Vec<UnencryptedLogin> = self
-            .logins
-            .logins
-            .iter()
-            .filter(|(p, _)| !self.salts.contains_key(*p))
-            .filter(|(p, _)| {
-                path == "/"
-                    || p.starts_with(&path)
-                        && (p.len() == path.len() || p.chars().nth(path.len()).unwrap() == '/')
+        let total = logins.len();
+        for (i, login) in logins.into_iter().enumerate() {
+            report(&mut progress, i, total)?;
+
+            if let Some(existing) = collision(&self.logins, &login.login_name) {
+                bail!(
+                    "Cannot add '{}', as it collides with the existing login '{}'",
+                    login.login_name,
+                    existing
+                );
+            }
+            if let Some(root) = hidden_collision(&self.hidden_logins, &login.login_name) {
+                bail!(
+                    "Cannot add '{}', as it collides with the hidden path '{}'",
+                    login.login_name,
+                    root
+                );
+            }
+
+            let (login_name, entry, salt) = encrypt_login(login, &mut keys)?;
+
+            self.logins.insert(login_name.clone(), entry);
+            self.salts.insert(login_name, salt);
+        }
+        report(&mut progress, total, total)?;
+
+        Ok(())
+    }
+
+    fn entry(&self, login_name: &str) -> anyhow::Result<&LoginEntry> {
+        self.logins
+            .logins
+            .get(login_name)
+            .ok_or_else(|| anyhow!("Path '{}' not found in logins", login_name))
+    }
+
+    fn entry_mut(&mut self, login_name: &str) -> anyhow::Result<&mut LoginEntry> {
+        self.logins
+            .logins
+            .get_mut(login_name)
+            .ok_or_else(|| anyhow!("Path '{}' not found in logins", login_name))
+    }
+
+    pub fn fields(&self, login_name: &str) -> anyhow::Result<Vec<(&String, &String)>> {
+        let entry = self.entry(login_name)?;
+
+        Ok(entry
+            .extra_fields
+            .iter()
+            .filter(|(k, _)| !entry.is_secret(k))
+            .collect())
+    }
+
+    pub fn secret_fields(&self, login_name: &str) -> anyhow::Result<&Vec<String>> {
+        Ok(&self.entry(login_name)?.secret_fields)
+    }
+
+    // created and modified timestamps, and when each previous password was replaced, newest first
+    pub fn history(
+        &self,
+        login_name: &str,
+    ) -> anyhow::Result<(Option<&String>, Option<&String>, Vec<&String>)> {
+        let entry = self.entry(login_name)?;
+
+        Ok((
+            entry.created.as_ref(),
+            entry.modified.as_ref(),
+            entry.password_history.iter().map(|p| &p.replaced).collect(),
+        ))
+    }
+
+    // `previous` 1 is the password before the current one
+    pub fn get_previous(
+        &self,
+        login_name: &str,
+        previous: usize,
+        master_password: Zeroizing<String>,
+    ) -> anyhow::Result<Zeroizing<String>> {
+        let entry = self.entry(login_name)?;
+        let index = previous
+            .checked_sub(1)
+            .ok_or_else(|| anyhow!("Previous passwords are counted from 1"))?;
+
+        match self.salts.get(login_name) {
+            Some(salt) => decrypt_previous(entry, salt, index, &mut self.keys(&master_password)),
+            None => entry
+                .password_history
+                .get(index)
+                .map(|p| Zeroizing::new(p.password.clone()))
+                .ok_or_else(|| anyhow!("No previous password {}", previous)),
+        }
+    }
+
+    pub fn get_field(&self, login_name: &str, key: &str) -> anyhow::Result<&String> {
+        let entry = self.entry(login_name)?;
+        if entry.is_secret(key) {
+            bail!(
+                "Field '{}' is secret. Use 'qass type {} --field {}' instead",
+                key,
+                login_name,
+                key
+            );
+        }
+
+        entry
+            .extra_fields
+            .get(key)
+            .ok_or_else(|| anyhow!("Field '{}' not found in '{}'", key, login_name))
+    }
+
+    pub fn set_field(
+        &mut self,
+        login_name: &str,
+        key: String,
+        value: String,
+    ) -> anyhow::Result<()> {
+        if key == "password" {
+            bail!("The password can only be changed with 'add'");
+        }
+        if RESERVED_FIELDS.contains(&key.as_str()) {
+            bail!("'{}' is reserved and can't be used as a field name", key);
+        }
+
+        let entry = self.entry_mut(login_name)?;
+        entry.secret_fields.retain(|f| *f != key);
+        entry.extra_fields.insert(key.clone(), value);
+        entry.modified = Some(now());
+
+        if let Some(salt_entry) = self.salts.get_mut(login_name) {
+            salt_entry.fields.shift_remove(&key);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_secret_field(
+        &mut self,
+        login_name: &str,
+        key: String,
+        value: Zeroizing<String>,
+        master_password: Zeroizing<String>,
+    ) -> anyhow::Result<()> {
+        if key == "password" {
+            bail!("The password can only be changed with 'add'");
+        }
+        if RESERVED_FIELDS.contains(&key.as_str()) {
+            bail!("'{}' is reserved and can't be used as a field name", key);
+        }
+
+        let mut keys = self.keys(&master_password);
+        let entry = self
+            .logins
+            .logins
+            .get_mut(login_name)
+            .ok_or_else(|| anyhow!("Path '{}' not found in logins", login_name))?;
+
+        match self.salts.get_mut(login_name) {
+            Some(salt_entry) => {
+                let (ciphertext, salt) = encrypt_secret(&value, &mut keys)?;
+                entry.extra_fields.insert(key.clone(), ciphertext);
+                salt_entry.fields.insert(key.clone(), salt);
+            }
+            None => {
+                entry.extra_fields.insert(key.clone(), value.to_string());
+            }
+        }
+
+        entry.modified = Some(now());
+        if !entry.secret_fields.contains(&key) {
+            entry.secret_fields.push(key);
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_field(&mut self, login_name: &str, key: &str) -> anyhow::Result<()> {
+        if key == "password" {
+            bail!("The password can not be removed from a login");
+        }
+
+        let entry = self.entry_mut(login_name)?;
+        entry.secret_fields.retain(|f| f != key);
+        entry
+            .extra_fields
+            .shift_remove(key)
+            .ok_or_else(|| anyhow!("Field '{}' not found in '{}'", key, login_name))?;
+        entry.modified = Some(now());
+
+        if let Some(salt_entry) = self.salts.get_mut(login_name) {
+            salt_entry.fields.shift_remove(key);
+        }
+
+        Ok(())
+    }
+
+    pub fn get(
+        &self,
+        login_name: String,
+        master_password: Zeroizing<String>,
+    ) -> anyhow::Result<Zeroizing<String>> {
+        self.get_secret(login_name, "password", master_password)
+    }
+
+    pub fn get_secret(
+        &self,
+        login_name: String,
+        field: &str,
+        master_password: Zeroizing<String>,
+    ) -> anyhow::Result<Zeroizing<String>> {
+        let login_entry = self.entry(&login_name)?;
+        let salt_entry = self
+            .salts
+            .get(&login_name)
+            .ok_or_else(|| anyhow!("Path '{}' not found in salts", login_name))?;
+
+        decrypt_field(
+            login_entry,
+            salt_entry,
+            field,
+            &mut self.keys(&master_password),
+        )
+    }
+
+    pub fn hide(&mut self, path: String, master_password: Zeroizing<String>) -> anyhow::Result<()> {
+        let logins = std::mem::take(&mut self.logins);
+        let salts = std::mem::take(&mut self.salts);
+
+        let (to_hide, rest): (Vec<(_, _)>, Vec<(_, _)>) = logins
+            .logins
+            .into_iter()
+            .partition(|(k, _)| is_under(&path, k));
+        let (to_hide, rest): (IndexMap<_, _>, IndexMap<_, _>) =
+            (IndexMap::from_iter(to_hide), IndexMap::from_iter(rest));
+
+        let mut salts_rest = salts;
+
+        let mut hidden = UnsaltedHiddenMap::new();
+        for (k, v) in to_hide {
+            if let Some(salt) = salts_rest.shift_remove(&k) {
+                hidden.insert(k, v, salt);
+            } else {
+                bail!("No salt found for {k}.");
+            }
+        }
+
+        let hidden_str = Zeroizing::new(serde_yaml::to_string(&hidden)?);
+        let (ciphertext, salt) = encrypt_secret(&hidden_str, &mut self.keys(&master_password))?;
+
+        self.hidden_logins.insert(
+            path,
+            HiddenMap {
+                logins: ciphertext,
+                salt,
+            },
+        );
+
+        self.logins = LoginMap::from(rest);
+        self.salts = salts_rest;
+
+        Ok(())
+    }
+
+    fn decrypt_hidden(
+        hidden_logins: &HiddenMapIndex,
+        path: &String,
+        keys: &mut Keys,
+    ) -> anyhow::Result<UnsaltedHiddenMap> {
+        let hidden_map = hidden_logins
+            .get(path)
+            .ok_or_else(|| anyhow!("Path '{}' not found", path))?;
+
+        let hidden_str = decrypt_secret(&hidden_map.logins, &hidden_map.salt, keys)?;
+        let hidden = UnsaltedHiddenMap::from_yaml(&hidden_str)?;
+
+        Ok(hidden)
+    }
+
+    pub fn unhide(
+        &mut self,
+        path: String,
+        master_password: Zeroizing<String>,
+    ) -> anyhow::Result<()> {
+        let hidden =
+            State::decrypt_hidden(&self.hidden_logins, &path, &mut self.keys(&master_password))?;
+
+        // stores from before this was checked on adding may have logins in the way
+        for login_key in hidden.logins.keys() {
+            if let Some(existing) = self
+                .logins
+                .logins
+                .keys()
+                .find(|p| is_under(p, login_key) || is_under(login_key, p))
+            {
+                bail!(
+                    "Cannot unhide '{}', as '{}' collides with the existing login '{}'. Move or remove it first",
+                    path,
+                    login_key,
+                    existing
+                );
+            }
+        }
+
+        for (login_key, entry) in hidden.logins {
+            self.logins.insert(login_key.clone(), entry.login);
+            self.salts.insert(login_key, entry.salt);
+        }
+
+        self.hidden_logins.shift_remove(&path);
+
+        Ok(())
+    }
+
+    pub fn get_hidden(
+        &self,
+        path: String,
+        field: &str,
+        master_password_unhide: Zeroizing<String>,
+        master_password: Zeroizing<String>,
+    ) -> anyhow::Result<Zeroizing<String>> {
+        let mut unhide_keys = self.keys(&master_password_unhide);
+        let hidden = self
+            .hidden_logins
+            .keys()
+            .filter(|p| path.starts_with(*p))
+            .find_map(|p| {
+                let h = State::decrypt_hidden(&self.hidden_logins, p, &mut unhide_keys);
+                h.ok().and_then(|h| h.logins.get(&path).cloned())
+            })
+            .ok_or_else(|| anyhow!("Path '{}' not found in logins", path))?;
+
+        decrypt_field(
+            &hidden.login,
+            &hidden.salt,
+            field,
+            &mut self.keys(&master_password),
+        )
+    }
+
+    // Like `add_many`, but skips logins whose path collides with another one instead of failing.
+    // Logins that already exist are replaced, keeping their old password in the history, unless
+    // `skip_existing` is set.
+    pub fn import(
+        &mut self,
+        logins: Vec<UnencryptedLogin>,
+        mut skipped: Vec<Skipped>,
+        master_password: Zeroizing<String>,
+        skip_existing: bool,
+        progress: Progress,
+    ) -> anyhow::Result<(usize, Vec<Skipped>)> {
+        let mut keys = self.keys(&master_password);
+        let mut accepted: Vec<UnencryptedLogin> = vec![];
+        for mut login in logins {
+            let name = &login.login_name;
+            let taken = self
+                .logins
+                .logins
+                .keys()
+                .filter(|p| p != &name)
+                .chain(accepted.iter().map(|l| &l.login_name))
+                .find(|p| is_under(p, name) || is_under(name, p));
+
+            let reason = match (taken, self.logins.logins.get(name)) {
+                (Some(p), _) if p == name => "duplicates an earlier path in the import".to_string(),
+                (Some(p), _) => format!("collides with '{}'", p),
+                _ if hidden_collision(&self.hidden_logins, name).is_some() => {
+                    "collides with a hidden path".to_string()
+                }
+                (None, Some(_)) if skip_existing => "already exists".to_string(),
+                (None, Some(entry)) => {
+                    let existing = match self.salts.get(name) {
+                        Some(salt) => decrypt_login(name.clone(), entry, salt, &mut keys).ok(),
+                        None => Some(UnencryptedLogin::from_cleartext(name.clone(), entry)),
+                    };
+                    let Some(existing) = existing else {
+                        skipped.push(Skipped {
+                            reason: "already exists with another master password".to_string(),
+                            source: name.clone(),
+                        });
+                        continue;
+                    };
+
+                    login.created = existing.created;
+                    login.modified = Some(now());
+                    login.history = existing.history;
+                    login.history.insert(0, (existing.password, now()));
+                    login.history.truncate(self.history_length);
+                    accepted.push(login);
+                    continue;
+                }
+                (None, None) => {
+                    accepted.push(login);
+                    continue;
+                }
+            };
+            skipped.push(Skipped {
+                reason,
+                source: name.clone(),
+            });
+        }
+        drop(keys);
+
+        let count = accepted.len();
+        self.add_many(accepted, master_password, progress)?;
+
+        Ok((count, skipped))
+    }
+
+    pub fn backup(&self, passphrase: &str) -> anyhow::Result<Archive> {
+        let backup = BackupRef {
+            info: StoreInfo {
+                version: self.version,
+                kdf: self.kdf.clone(),
+                kdf_mode: self.kdf_mode,
+                history_length: self.history_length,
+            },
+            logins: &self.logins,
+            salts: &self.salts,
+            hidden_logins: &self.hidden_logins,
+        };
+
+        Archive::seal(&backup, passphrase, self.kdf.clone())
+    }
+
+    pub fn diff_backup(&self, backup: &Backup) -> BackupDiff {
+        let mut diff = BackupDiff::default();
+
+        for (name, entry) in &backup.logins.logins {
+            match self.logins.logins.get(name) {
+                None => diff.only_in_backup.push(name.clone()),
+                Some(current)
+                    if current != entry || self.salts.get(name) != backup.salts.get(name) =>
+                {
+                    diff.changed.push(name.clone())
+                }
+                _ => {}
+            }
+        }
+        for (root, hidden) in &backup.hidden_logins {
+            let name = format!("{} (hidden)", root);
+            match self.hidden_logins.get(root) {
+                None => diff.only_in_backup.push(name),
+                Some(current) if current != hidden => diff.changed.push(name),
+                _ => {}
+            }
+        }
+
+        for name in self.logins.logins.keys() {
+            if !backup.logins.logins.contains_key(name) {
+                diff.only_in_store.push(name.clone());
+            }
+        }
+        for root in self.hidden_logins.keys() {
+            if !backup.hidden_logins.contains_key(root) {
+                diff.only_in_store.push(format!("{} (hidden)", root));
+            }
+        }
+
+        diff
+    }
+
+    // Makes the store identical to the backup.
+    pub fn replace_with_backup(&mut self, backup: Backup) -> anyhow::Result<()> {
+        backup.check_version()?;
+
+        self.kdf = backup.info.kdf;
+        self.kdf_mode = backup.info.kdf_mode;
+        self.history_length = backup.info.history_length;
+        self.logins = backup.logins;
+        self.salts = backup.salts;
+        self.hidden_logins = backup.hidden_logins;
+
+        Ok(())
+    }
+
+    // Only adds what is missing from the store and keeps its version of changed entries. Logins
+    // that don't decrypt with the master password, or whose path collides with an existing one,
+    // are left out. Hidden paths are added as they are, they have passwords of their own.
+    pub fn restore(
+        &mut self,
+        backup: Backup,
+        master_password: Zeroizing<String>,
+        mut progress: Progress,
+    ) -> anyhow::Result<(usize, Vec<Skipped>)> {
+        backup.check_version()?;
+
+        let mut keys = self.keys(&master_password);
+        let mut added = 0;
+        let mut skipped = vec![];
+        let mut skip = |source: String, reason: &str| {
+            skipped.push(Skipped {
+                source,
+                reason: reason.to_string(),
+            })
+        };
+
+        let mut salts = backup.salts;
+        let total = backup.logins.logins.len();
+        for (i, (name, entry)) in backup.logins.logins.into_iter().enumerate() {
+            report(&mut progress, i, total)?;
+
+            if self.logins.logins.contains_key(&name) {
+                continue;
+            }
+            if collision(&self.logins, &name).is_some()
+                || hidden_collision(&self.hidden_logins, &name).is_some()
+            {
+                skip(name, "collides with an existing path");
+                continue;
+            }
+
+            let salt = salts.shift_remove(&name);
+            if let Some(salt) = &salt {
+                if decrypt_field(&entry, salt, "password", &mut keys).is_err() {
+                    skip(name, "is encrypted with another master password");
+                    continue;
+                }
+            }
+
+            if let Some(salt) = salt {
+                self.salts.insert(name.clone(), salt);
+            }
+            self.logins.insert(name, entry);
+            added += 1;
+        }
+        report(&mut progress, total, total)?;
+
+        for (root, hidden) in backup.hidden_logins {
+            if self.hidden_logins.contains_key(&root) {
+                continue;
+            }
+            if self
+                .logins
+                .logins
+                .keys()
+                .any(|p| is_under(&root, p) || is_under(p, &root))
+                || self
+                    .hidden_logins
+                    .keys()
+                    .any(|r| is_under(&root, r) || is_under(r, &root))
+            {
+                skip(
+                    format!("{} (hidden)", root),
+                    "collides with an existing path",
+                );
+                continue;
+            }
+
+            self.hidden_logins.insert(root, hidden);
+            added += 1;
+        }
+
+        Ok((added, skipped))
+    }
+
+    // Merges the logins and hidden paths of `other` entry by entry, a login together with its salt.
+    // With a `base`, the state both sides started from, changes on one side are taken over and
+    // only paths changed on both sides need `resolve`. Without it, removals can't be told from
+    // additions, so paths only present here are kept and every difference is a conflict.
+    pub fn merge(
+        &mut self,
+        other: &State,
+        base: Option<&State>,
+        resolve: Resolve,
+    ) -> anyhow::Result<MergeSummary> {
+        let mut summary = MergeSummary::default();
+
+        let login_changes = merge_maps(
+            &self.login_items(),
+            &other.login_items(),
+            base.map(|b| b.login_items()).as_ref(),
+            |path| path.to_string(),
+            resolve,
+        )?;
+        for (path, value) in login_changes {
+            match value {
+                Some((entry, salt)) => {
+                    if !self.logins.logins.contains_key(&path)
+                        && (collision(&self.logins, &path).is_some()
+                            || hidden_collision(&self.hidden_logins, &path).is_some())
+                    {
+                        summary.collisions.push(path);
+                        continue;
+                    }
+
+                    match salt {
+                        Some(salt) => self.salts.insert(path.clone(), salt),
+                        None => self.salts.shift_remove(&path),
+                    };
+                    self.logins.insert(path.clone(), entry);
+                }
+                None => {
+                    self.logins.logins.shift_remove(&path);
+                    self.salts.shift_remove(&path);
+                    summary.taken.push(format!("{} (removed)", path));
+                    continue;
+                }
+            }
+            summary.taken.push(path);
+        }
+
+        let hidden_changes = merge_maps(
+            &self.hidden_logins,
+            &other.hidden_logins,
+            base.map(|b| &b.hidden_logins),
+            |root| format!("{} (hidden)", root),
+            resolve,
+        )?;
+        for (root, value) in hidden_changes {
+            let name = format!("{} (hidden)", root);
+            match value {
+                Some(hidden) => {
+                    if !self.hidden_logins.contains_key(&root)
+                        && (self
+                            .logins
+                            .logins
+                            .keys()
+                            .any(|p| is_under(&root, p) || is_under(p, &root))
+                            || self
+                                .hidden_logins
+                                .keys()
+                                .any(|r| is_under(&root, r) || is_under(r, &root)))
+                    {
+                        summary.collisions.push(name);
+                        continue;
+                    }
+
+                    self.hidden_logins.insert(root, hidden.clone());
+                }
+                None => {
+                    self.hidden_logins.shift_remove(&root);
+                    summary.taken.push(format!("{} (removed)", name));
+                    continue;
+                }
+            }
+            summary.taken.push(name);
+        }
+
+        Ok(summary)
+    }
+
+    fn login_items(&self) -> IndexMap<String, (LoginEntry, Option<SaltEntry>)> {
+        self.logins
+            .logins
+            .iter()
+            .map(|(path, entry)| (path.clone(), (entry.clone(), self.salts.get(path).cloned())))
+            .collect()
+    }
+
+    pub fn list(&self) -> Vec<String> {
+        let logins: Vec<String> = self
+            .logins
+            .logins
+            .keys()
+            .filter(|key| self.salts.contains_key(*key))
+            .cloned()
+            .collect();
+
+        logins
+    }
+
+    fn ensure_not_hidden(&self, path: &str) -> anyhow::Result<()> {
+        if let Some(root) = self.hidden_logins.keys().find(|root| is_under(root, path)) {
+            bail!(
+                "Path '{}' is hidden under '{}'. Unhide it first",
+                path,
+                root
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, path: String) -> anyhow::Result<Vec<String>> {
+        // as in `rename`, "work/" means "work", only "/" itself stands for the whole store
+        let path = match path.trim_end_matches('/') {
+            "" => "/".to_string(),
+            path => path.to_string(),
+        };
+        self.ensure_not_hidden(&path)?;
+
+        let removed: Vec<String> = self
+            .logins
+            .logins
+            .keys()
+            .filter(|p| is_under(&path, p))
+            .cloned()
+            .collect();
+
+        if removed.is_empty() {
+            bail!("Path '{}' not found in logins", path);
+        }
+
+        self.logins.logins.retain(|p, _| !is_under(&path, p));
+        self.salts.retain(|p, _| !is_under(&path, p));
+
+        Ok(removed)
+    }
+
+    pub fn rename(&mut self, from: String, to: String) -> anyhow::Result<Vec<(String, String)>> {
+        // "work/" means the same as "work", and mustn't produce paths like "work//x"
+        let from = from.trim_end_matches('/').to_string();
+        let to = to.trim_end_matches('/').to_string();
+        if from.is_empty() || to.is_empty() {
+            bail!("Cannot move the root of the store");
+        }
+        if is_under(&from, &to) {
+            bail!("Cannot move '{}' into itself", from);
+        }
+
+        self.ensure_not_hidden(&from)?;
+        self.ensure_not_hidden(&to)?;
+
+        let moves: Vec<(String, String)> = self
+            .logins
+            .logins
+            .keys()
+            .filter(|p| is_under(&from, p))
+            .map(|p| (p.clone(), format!("{}{}", to, &p[from.len()..])))
+            .collect();
+
+        if moves.is_empty() {
+            bail!("Path '{}' not found in logins", from);
+        }
+
+        for (_, new_path) in &moves {
+            if let Some(existing) = self
+                .logins
+                .logins
+                .keys()
+                .filter(|p| !is_under(&from, p))
+                .find(|p| is_under(p, new_path) || is_under(new_path, p))
+            {
+                bail!(
+                    "Cannot move to '{}', as it collides with the existing login '{}'",
+                    new_path,
+                    existing
+                );
+            }
+            if let Some(root) = hidden_collision(&self.hidden_logins, new_path) {
+                bail!(
+                    "Cannot move to '{}', as it collides with the hidden path '{}'",
+                    new_path,
+                    root
+                );
+            }
+        }
+
+        for (old_path, new_path) in &moves {
+            if let Some(entry) = self.logins.logins.shift_remove(old_path) {
+                self.logins.insert(new_path.clone(), entry);
+            }
+            if let Some(salt) = self.salts.shift_remove(old_path) {
+                self.salts.insert(new_path.clone(), salt);
+            }
+        }
+
+        Ok(moves)
+    }
+
+    pub fn unlock(
+        &mut self,
+        path: String,
+        master_password: Zeroizing<String>,
+        mut progress: Progress,
+    ) -> anyhow::Result<usize> {
+        let logins: Vec<String> = self
+            .salts
+            .keys()
+            .filter(|p| is_under(&path, p))
+            .cloned()
+            .collect();
+
+        let mut keys = self.keys(&master_password);
+        let mut unlocked_count = 0;
+
+        let total = logins.len();
+        for (i, login) in logins.into_iter().enumerate() {
+            report(&mut progress, i, total)?;
+
+            let (Some(entry), Some(salt)) =
+                (self.logins.logins.get(&login), self.salts.get(&login))
+            else {
+                continue;
+            };
+
+            if let Ok(cleartext) = decrypt_login(login.clone(), entry, salt, &mut keys) {
+                let (login, entry) = cleartext.into_cleartext();
+                self.salts.shift_remove(&login);
+                self.logins.insert(login, entry);
+                unlocked_count += 1;
+            }
+        }
+        report(&mut progress, total, total)?;
+
+        Ok(unlocked_count)
+    }
+
+    // decrypts the logins under `path` in memory, the second list holds the ones that couldn't be
+    pub fn export(
+        &self,
+        path: &str,
+        master_password: Zeroizing<String>,
+        progress: Progress,
+    ) -> anyhow::Result<(Vec<UnencryptedLogin>, Vec<String>)> {
+        self.decrypt_where(path, &master_password, progress, decrypt_login)
+    }
+
+    // like `export`, but only decrypts the current passwords, which saves a key derivation per
+    // secret field and previous password in per-entry mode. Secret fields are left out and
+    // previous passwords empty, their timestamps are kept.
+    pub fn audit_logins(
+        &self,
+        path: &str,
+        master_password: Zeroizing<String>,
+        progress: Progress,
+    ) -> anyhow::Result<(Vec<UnencryptedLogin>, Vec<String>)> {
+        self.decrypt_where(
+            path,
+            &master_password,
+            progress,
+            |name, entry, salt, keys| {
+                let mut login = UnencryptedLogin::from_cleartext(name, entry);
+                login.password = decrypt_field(entry, salt, "password", keys)?;
+                login.secret_fields.clear();
+                for (previous, _) in login.history.iter_mut() {
+                    *previous = Zeroizing::default();
+                }
+                Ok(login)
+            },
+        )
+    }
+
+    fn decrypt_where(
+        &self,
+        path: &str,
+        master_password: &str,
+        mut progress: Progress,
+        decrypt: impl Fn(String, &LoginEntry, &SaltEntry, &mut Keys) -> anyhow::Result<UnencryptedLogin>,
+    ) -> anyhow::Result<(Vec<UnencryptedLogin>, Vec<String>)> {
+        let names: Vec<&String> = self
+            .logins
+            .logins
+            .keys()
+            .filter(|p| is_under(path, p))
+            .collect();
+
+        let mut keys = self.keys(master_password);
+        let mut logins = vec![];
+        let mut failed = vec![];
+
+        let total = names.len();
+        for (i, name) in names.into_iter().enumerate() {
+            report(&mut progress, i, total)?;
+
+            let entry = &self.logins.logins[name];
+            match self.salts.get(name) {
+                None => logins.push(UnencryptedLogin::from_cleartext(name.clone(), entry)),
+                Some(salt) => match decrypt(name.clone(), entry, salt, &mut keys) {
+                    Ok(login) => logins.push(login),
+                    Err(_) => failed.push(name.clone()),
+                },
+            }
+        }
+        report(&mut progress, total, total)?;
+
+        Ok((logins, failed))
+    }
+
+    pub fn sync(
+        &mut self,
+        path: String,
+        master_password: Zeroizing<String>,
+        progress: Progress,
+    ) -> anyhow::Result<usize> {
+        self.salts = std::mem::take(&mut self.salts)
+            .into_iter()
+            .filter(|(p, _)| self.logins.logins.contains_key(p))
+            .collect();
+
+        let to_add: Vec<UnencryptedLogin> = self
+            .logins
+            .logins
+            .iter()
+            .filter(|(p, _)| !self.salts.contains_key(*p))
+            .filter(|(p, _)| is_under(&path, p))
+            .map(|(p, s)| UnencryptedLogin::from_cleartext(p.to_owned(), s))
+            .collect();
+
+        let count = to_add.len();
+        self.add_many(to_add, master_password, progress)?;
+
+        Ok(count)
+    }
+}
+
+fn encrypt_secret(secret: &str, keys: &mut Keys) -> anyhow::Result<(String, SaltEntry)> {
+    let cipher = Cipher::default();
+
+    let salt = generate_salt();
+    let (key, batch_salt) = keys.encryption_key(&salt)?;
+    let (nonce, ciphertext) = encrypt(secret, &key, cipher)?;
+
+    Ok((
+        b64.encode(ciphertext),
+        SaltEntry {
+            nonce: b64.encode(nonce),
+            salt,
+            kdf: Some(keys.kdf().clone()),
+            cipher: Some(cipher),
+            batch_salt,
+            fields: IndexMap::new(),
+            history: vec![],
+        },
+    ))
+}
+
+fn decrypt_secret(
+    ciphertext: &str,
+    salt: &SaltEntry,
+    keys: &mut Keys,
+) -> anyhow::Result<Zeroizing<String>> {
+    let key = keys.decryption_key(salt)?;
+    let ciphertext = b64.decode(ciphertext)?;
+    let nonce = b64.decode(&salt.nonce)?;
+
+    Ok(Zeroizing::new(decrypt(
+        &ciphertext,
+        &key,
+        &nonce,
+        salt.cipher(),
+    )?))
+}
+
+fn decrypt_field(
+    entry: &LoginEntry,
+    salt: &SaltEntry,
+    field: &str,
+    keys: &mut Keys,
+) -> anyhow::Result<Zeroizing<String>> {
+    if field == "password" {
+        return decrypt_secret(&entry.password, salt, keys);
+    }
+    if !entry.is_secret(field) {
+        bail!("Field '{}' is not secret", field);
+    }
+
+    let ciphertext = entry
+        .extra_fields
+        .get(field)
+        .ok_or_else(|| anyhow!("Field '{}' not found", field))?;
+    let field_salt = salt
+        .fields
+        .get(field)
+        .ok_or_else(|| anyhow!("No salt found for field '{}'", field))?;
+
+    decrypt_secret(ciphertext, field_salt, keys)
+}
+
+// `index` 0 is the password before the current one
+fn decrypt_previous(
+    entry: &LoginEntry,
+    salt: &SaltEntry,
+    index: usize,
+    keys: &mut Keys,
+) -> anyhow::Result<Zeroizing<String>> {
+    let (previous, previous_salt) = entry
+        .password_history
+        .get(index)
+        .zip(salt.history.get(index))
+        .ok_or_else(|| anyhow!("No previous password {}", index + 1))?;
+
+    decrypt_secret(&previous.password, previous_salt, keys)
+}
+
+fn encrypt_login(
+    mut login: UnencryptedLogin,
+    keys: &mut Keys,
+) -> anyhow::Result<(String, LoginEntry, SaltEntry)> {
+    login.rename_reserved_fields();
+    let (password, mut salt) = encrypt_secret(&login.password, keys)?;
+
+    let mut entry = LoginEntry {
+        password,
+        created: login.created.or_else(|| Some(now())),
+        modified: login.modified.or_else(|| Some(now())),
+        password_history: vec![],
+        extra_fields: login.extra_fields,
+        secret_fields: vec![],
+    };
+    for (key, value) in login.secret_fields {
+        let (ciphertext, field_salt) = encrypt_secret(&value, keys)?;
+        entry.extra_fields.insert(key.clone(), ciphertext);
+        entry.secret_fields.push(key.clone());
+        salt.fields.insert(key, field_salt);
+    }
+    for (previous, replaced) in login.history {
+        let (ciphertext, previous_salt) = encrypt_secret(&previous, keys)?;
+        entry.password_history.push(PreviousPassword {
+            password: ciphertext,
+            replaced,
+        });
+        salt.history.push(previous_salt);
+    }
+
+    Ok((login.login_name, entry, salt))
+}
+
+fn decrypt_login(
+    login_name: String,
+    entry: &LoginEntry,
+    salt: &SaltEntry,
+    keys: &mut Keys,
+) -> anyhow::Result<UnencryptedLogin> {
+    let mut login = UnencryptedLogin::from_cleartext(login_name, entry);
+
+    login.password = decrypt_field(entry, salt, "password", keys)?;
+    for (key, value) in login.secret_fields.iter_mut() {
+        *value = decrypt_field(entry, salt, key, keys)?;
+    }
+    for (i, (previous, _)) in login.history.iter_mut().enumerate() {
+        *previous = decrypt_previous(entry, salt, i, keys)?;
+    }
+
+    Ok(login)
+}
+
+// Returns the keys that take the value of `theirs`, `None` meaning removed.
+fn merge_maps<V: PartialEq + Clone>(
+    ours: &IndexMap<String, V>,
+    theirs: &IndexMap<String, V>,
+    base: Option<&IndexMap<String, V>>,
+    label: impl Fn(&str) -> String,
+    resolve: Resolve,
+) -> anyhow::Result<Vec<(String, Option<V>)>> {
+    let describe = |value: Option<&V>, base_value: Option<&V>| match (value, base_value) {
+        (None, _) => "removed",
+        (Some(_), None) if base.is_some() => "added",
+        _ => "changed",
+    };
+
+    let mut changes = vec![];
+    for key in ours
+        .keys()
+        .chain(theirs.keys().filter(|k| !ours.contains_key(*k)))
+    {
+        let (o, t) = (ours.get(key), theirs.get(key));
+        let b = base.and_then(|b| b.get(key));
+        if o == t {
+            continue;
+        }
+
+        let take_theirs = match base {
+            Some(_) if o == b => true,
+            Some(_) if t == b => false,
+            None if t.is_none() => false,
+            None if o.is_none() => true,
+            _ => {
+                let conflict = Conflict {
+                    path: label(key),
+                    ours: describe(o, b),
+                    theirs: describe(t, b),
+                };
+                resolve(&conflict)? == Side::Theirs
+            }
+        };
+        if take_theirs {
+            changes.push((key.clone(), t.cloned()));
+        }
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER: &str = "master";
+
+    fn master() -> Zeroizing<String> {
+        Zeroizing::new(MASTER.to_string())
+    }
+
+    // a store with cheap key derivation holding the given logins, all with the password "pw"
+    fn store(paths: &[&str]) -> (tempfile::TempDir, State) {
+        let dir = tempfile::tempdir().unwrap();
+        State::init(dir.path()).unwrap();
+
+        let mut state = State::load_mut(dir.path()).unwrap();
+        state
+            .set_kdf(KdfParams {
+                m_cost: 8,
+                t_cost: 1,
+                ..Default::default()
             })
-            .map(|(p, s)| UnencryptedLogin {
-                login_name: p.to_owned(),
-                password: Zeroizing::new(s.password.to_owned()),
-                extra_fields: s.extra_fields.to_owned(),
+            .unwrap();
+
+        let logins = paths
+            .iter()
+            .map(|p| UnencryptedLogin::new(p.to_string(), Zeroizing::new("pw".to_string())))
+            .collect();
+        state.add_many(logins, master(), &mut |_, _| true).unwrap();
+
+        (dir, state)
+    }
+
+    fn paths(state: &State) -> Vec<&str> {
+        state.logins.logins.keys().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn remove_deletes_subtree_with_salts() {
+        let (_dir, mut state) = store(&["a/x", "a/y", "ab/z", "b"]);
+
+        let removed = state.remove("a".to_string()).unwrap();
+
+        assert_eq!(removed, ["a/x", "a/y"]);
+        assert_eq!(paths(&state), ["ab/z", "b"]);
+        assert_eq!(
+            state.salts.keys().collect::<Vec<_>>(),
+            [&"ab/z".to_string(), &"b".to_string()]
+        );
+    }
+
+    #[test]
+    fn remove_single_login() {
+        let (_dir, mut state) = store(&["a/x", "a/y"]);
+
+        assert_eq!(state.remove("a/x".to_string()).unwrap(), ["a/x"]);
+        assert_eq!(paths(&state), ["a/y"]);
+    }
+
+    #[test]
+    fn remove_ignores_trailing_slash() {
+        let (_dir, mut state) = store(&["a/x", "a/y", "ab/z"]);
+
+        assert_eq!(state.remove("a/".to_string()).unwrap(), ["a/x", "a/y"]);
+        assert_eq!(state.remove("ab/z//".to_string()).unwrap(), ["ab/z"]);
+        assert!(paths(&state).is_empty());
+    }
+
+    #[test]
+    fn remove_missing_path_fails() {
+        let (_dir, mut state) = store(&["a/x"]);
+
+        assert!(state.remove("b".to_string()).is_err());
+        assert!(state.remove("a/x/y".to_string()).is_err());
+        assert_eq!(paths(&state), ["a/x"]);
+    }
+
+    #[test]
+    fn remove_refuses_hidden_paths() {
+        let (_dir, mut state) = store(&["bank/x", "mail/y"]);
+        state.hide("bank".to_string(), master()).unwrap();
+
+        assert!(state.remove("bank".to_string()).is_err());
+        assert!(state.remove("bank/x".to_string()).is_err());
+        assert!(state.hidden_logins.contains_key("bank"));
+    }
+
+    #[test]
+    fn removal_is_saved() {
+        let (dir, mut state) = store(&["a/x", "b"]);
+        state.remove("a".to_string()).unwrap();
+        state.save("Remove a").unwrap();
+        drop(state);
+
+        let state = State::load(dir.path()).unwrap();
+        assert_eq!(paths(&state), ["b"]);
+        assert!(!state.salts.contains_key("a/x"));
+    }
+
+    #[test]
+    fn rename_moves_subtree_with_salts() {
+        let (_dir, mut state) = store(&["a/x", "a/y/z", "ab"]);
+        let salt = state.salts["a/x"].clone();
+
+        state.rename("a".to_string(), "work/a".to_string()).unwrap();
+
+        assert_eq!(paths(&state), ["ab", "work/a/x", "work/a/y/z"]);
+        assert_eq!(state.salts["work/a/x"], salt);
+        assert!(!state.salts.contains_key("a/x"));
+    }
+
+    #[test]
+    fn rename_ignores_trailing_slashes() {
+        let (_dir, mut state) = store(&["a/x"]);
+
+        let moves = state.rename("a/".to_string(), "work/".to_string()).unwrap();
+
+        assert_eq!(moves, [("a/x".to_string(), "work/x".to_string())]);
+        assert_eq!(paths(&state), ["work/x"]);
+    }
+
+    #[test]
+    fn rename_refuses_collisions() {
+        let (_dir, mut state) = store(&["a/x", "b/x", "c"]);
+
+        assert!(state.rename("a".to_string(), "b".to_string()).is_err());
+        assert!(state.rename("a".to_string(), "c/d".to_string()).is_err());
+        assert!(state.rename("a".to_string(), "a/b".to_string()).is_err());
+        assert!(state.rename("/".to_string(), "d".to_string()).is_err());
+        assert_eq!(paths(&state), ["a/x", "b/x", "c"]);
+    }
+
+    #[test]
+    fn rename_refuses_hidden_roots_under_destination() {
+        let (_dir, mut state) = store(&["a/secret/x", "a/y", "work/secret/z"]);
+        state.hide("work/secret".to_string(), master()).unwrap();
+
+        assert!(state.rename("a".to_string(), "work".to_string()).is_err());
+        assert!(state
+            .rename("a/y".to_string(), "work/secret".to_string())
+            .is_err());
+        assert!(state
+            .rename("a/y".to_string(), "work/y".to_string())
+            .is_ok());
+    }
+
+    #[test]
+    fn add_refuses_hidden_roots() {
+        let (dir, mut state) = store(&["bank/x", "mail"]);
+        state.hide("bank".to_string(), master()).unwrap();
+
+        for path in ["bank", "bank/y", "bank/x/z"] {
+            let error = state.add(path.to_string(), master(), master()).unwrap_err();
+            assert!(
+                error.to_string().contains("hidden path 'bank'"),
+                "{}",
+                error
+            );
+        }
+        state.add("bankx".to_string(), master(), master()).unwrap();
+        state.save("Add bankx").unwrap();
+
+        state.unhide("bank".to_string(), master()).unwrap();
+        state.save("Unhide bank").unwrap();
+        drop(state);
+        assert_eq!(
+            paths(&State::load(dir.path()).unwrap()),
+            ["mail", "bankx", "bank/x"]
+        );
+    }
+
+    #[test]
+    fn import_skips_hidden_roots() {
+        let (_dir, mut state) = store(&["bank/x"]);
+        state.hide("bank".to_string(), master()).unwrap();
+
+        let (count, skipped) = state
+            .import(
+                vec![login("bank", "a"), login("bank/x/y", "b"), login("c", "c")],
+                vec![],
+                master(),
+                false,
+                &mut |_, _| true,
+            )
+            .unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(paths(&state), ["c"]);
+        assert!(skipped
+            .iter()
+            .all(|s| s.reason == "collides with a hidden path"));
+        assert_eq!(skipped.len(), 2);
+    }
+
+    #[test]
+    fn unhide_refuses_logins_in_the_way() {
+        let (_dir, mut state) = store(&["bank/x"]);
+        state.hide("bank".to_string(), master()).unwrap();
+        // stores written before adding checked hidden roots can already look like this
+        let (_other_dir, other) = store(&["bank"]);
+        state.logins = other.logins;
+        state.salts = other.salts;
+
+        assert!(state.unhide("bank".to_string(), master()).is_err());
+        assert!(state.hidden_logins.contains_key("bank"));
+        assert_eq!(paths(&state), ["bank"]);
+    }
+
+    #[test]
+    fn logins_in_the_way_fail_to_save() {
+        for paths in [["bank", "bank/x"], ["bank/x", "bank"]] {
+            let logins = LoginMap::from(
+                paths
+                    .iter()
+                    .map(|p| login(p, "pw").into_cleartext())
+                    .collect::<IndexMap<_, _>>(),
+            );
+
+            assert!(serde_yaml::to_string(&logins).is_err());
+        }
+    }
+
+    fn saved_version(dir: &Path) -> u32 {
+        load_from_yaml::<StoreInfo>(&dir.join("store.yml"))
+            .unwrap()
+            .version
+    }
+
+    #[test]
+    fn save_keeps_older_format_version() {
+        let dir = tempfile::tempdir().unwrap();
+        State::init(dir.path()).unwrap();
+        let mut state = State::load_mut(dir.path()).unwrap();
+        state.version = 1;
+        for path in ["a", "b"] {
+            let entry: LoginEntry = serde_yaml::from_str("password: pw").unwrap();
+            state.logins.insert(path.to_string(), entry);
+        }
+        state.save("Add legacy logins").unwrap();
+
+        state.remove("a".to_string()).unwrap();
+        state.save("Remove a").unwrap();
+        assert_eq!(saved_version(dir.path()), 1);
+
+        state
+            .set_kdf(KdfParams {
+                m_cost: 8,
+                t_cost: 1,
+                ..Default::default()
             })
+            .unwrap();
+        state
+            .add("c".to_string(), Zeroizing::new("pw".to_string()), master())
+            .unwrap();
+        state.save("Add c").unwrap();
+        assert_eq!(saved_version(dir.path()), FORMAT_VERSION);
+    }
+
+    #[test]
+    fn migrate_raises_format_version() {
+        let (dir, mut state) = store(&[]);
+        state.version = 2;
+
+        state.migrate();
+        state.save("Migrate").unwrap();
+
+        assert_eq!(saved_version(dir.path()), FORMAT_VERSION);
+    }
+
+    #[test]
+    fn rekey_kdf_follows_mode_changes() {
+        let (_dir, mut state) = store(&["a", "b"]);
+        assert!(state.salts["a"].batch_salt.is_none());
+
+        let (count, _) = state
+            .rekey_kdf("/".to_string(), master(), &mut |_, _| true)
+            .unwrap();
+        assert_eq!(count, 0);
+
+        state.set_kdf_mode(KdfMode::Batch);
+        let (count, failed) = state
+            .rekey_kdf("/".to_string(), master(), &mut |_, _| true)
+            .unwrap();
+        assert_eq!((count, failed.len()), (2, 0));
+        assert!(state.salts.values().all(|s| s.batch_salt.is_some()));
+        assert_eq!(&*state.get("a".to_string(), master()).unwrap(), "pw");
+
+        state.set_kdf_mode(KdfMode::PerEntry);
+        let (count, _) = state
+            .rekey_kdf("/".to_string(), master(), &mut |_, _| true)
+            .unwrap();
+        assert_eq!(count, 2);
+        assert!(state.salts.values().all(|s| s.batch_salt.is_none()));
+    }
+
+    fn login(path: &str, password: &str) -> UnencryptedLogin {
+        UnencryptedLogin::new(path.to_string(), Zeroizing::new(password.to_string()))
+    }
+
+    #[test]
+    fn import_replaces_existing_logins() {
+        let (_dir, mut state) = store(&["a", "b/x"]);
+
+        let (count, skipped) = state
+            .import(
+                vec![login("a", "new"), login("c", "pw")],
+                vec![],
+                master(),
+                false,
+                &mut |_, _| true,
+            )
+            .unwrap();
+
+        assert_eq!(count, 2);
+        assert!(skipped.is_empty());
+        assert_eq!(&*state.get("a".to_string(), master()).unwrap(), "new");
+        assert_eq!(&*state.get_previous("a", 1, master()).unwrap(), "pw");
+        assert_eq!(paths(&state), ["a", "b/x", "c"]);
+    }
+
+    #[test]
+    fn import_skips_existing_logins_on_request() {
+        let (_dir, mut state) = store(&["a"]);
+
+        let (count, skipped) = state
+            .import(
+                vec![login("a", "new")],
+                vec![],
+                master(),
+                true,
+                &mut |_, _| true,
+            )
+            .unwrap();
+
+        assert_eq!(count, 0);
+        assert_eq!(skipped[0].reason, "already exists");
+        assert_eq!(&*state.get("a".to_string(), master()).unwrap(), "pw");
+    }
+
+    #[test]
+    fn import_skips_collisions_and_duplicates() {
+        let (_dir, mut state) = store(&["b/x"]);
+
+        let (count, skipped) = state
+            .import(
+                vec![
+                    login("b", "pw"),
+                    login("c", "pw"),
+                    login("c", "pw2"),
+                    login("c/d", "pw"),
+                ],
+                vec![],
+                master(),
+                false,
+                &mut |_, _| true,
+            )
+            .unwrap();
+
+        assert_eq!(count, 1);
+        let reasons: Vec<_> = skipped.iter().map(|s| s.reason.as_str()).collect();
+        assert_eq!(
+            reasons,
+            [
+                "collides with 'b/x'",
+                "duplicates an earlier path in the import",
+                "collides with 'c'"
+            ]
+        );
+        assert_eq!(paths(&state), ["b/x", "c"]);
+    }
+
+    #[test]
+    fn import_skips_logins_of_another_master_password() {
+        let (_dir, mut state) = store(&["a"]);
+
+        let (count, skipped) = state
+            .import(
+                vec![login("a", "new")],
+                vec![],
+                Zeroizing::new("other".to_string()),
+                false,
+                &mut |_, _| true,
+            )
+            .unwrap();
+
+        assert_eq!(count, 0);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(&*state.get("a".to_string(), master()).unwrap(), "pw");
+    }
+
+    // a backup of the logins "a" and "b/x" and of "c" under another master password
+    fn backup() -> Backup {
+        let (_dir, mut state) = store(&["a", "b/x"]);
+        let other = UnencryptedLogin::new("c".to_string(), Zeroizing::new("pw".to_string()));
+        state
+            .add_many(
+                vec![other],
+                Zeroizing::new("other".to_string()),
+                &mut |_, _| true,
+            )
+            .unwrap();
+
+        state
+            .backup("passphrase")
+            .unwrap()
+            .open("passphrase")
+            .unwrap()
+    }
+
+    fn sources(skipped: &[Skipped]) -> Vec<(&str, &str)> {
+        skipped
+            .iter()
+            .map(|s| (s.source.as_str(), s.reason.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn restore_adds_logins_readable_with_the_master_password() {
+        let (_dir, mut state) = store(&[]);
+
+        let (added, skipped) = state.restore(backup(), master(), &mut |_, _| true).unwrap();
+
+        assert_eq!(added, 2);
+        assert_eq!(paths(&state), ["a", "b/x"]);
+        assert_eq!(
+            sources(&skipped),
+            [("c", "is encrypted with another master password")]
+        );
+        assert_eq!(
+            state.get("b/x".to_string(), master()).unwrap().as_str(),
+            "pw"
+        );
+    }
+
+    #[test]
+    fn restore_skips_existing_and_colliding_paths() {
+        let (_dir, mut state) = store(&["a", "b"]);
+
+        let (added, skipped) = state.restore(backup(), master(), &mut |_, _| true).unwrap();
+
+        assert_eq!(added, 0);
+        assert_eq!(paths(&state), ["a", "b"]);
+        assert_eq!(
+            sources(&skipped),
+            [
+                ("b/x", "collides with an existing path"),
+                ("c", "is encrypted with another master password")
+            ]
+        );
+    }
+
+    #[test]
+    fn newer_backups_are_refused() {
+        let (_dir, mut state) = store(&["d"]);
+        let newer = || {
+            let mut backup = backup();
+            backup.info.version = FORMAT_VERSION + 1;
+            backup
+        };
+
+        assert!(newer().check_version().is_err());
+        assert!(state.restore(newer(), master(), &mut |_, _| true).is_err());
+        assert!(state.replace_with_backup(newer()).is_err());
+        assert_eq!(paths(&state), ["d"]);
+
+        state.replace_with_backup(backup()).unwrap();
+        assert_eq!(paths(&state), ["a", "b/x", "c"]);
+    }
+
+    fn map<V: Clone>(items: &[(&str, V)]) -> IndexMap<String, V> {
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    }
+
+    type Changes<V> = Vec<(String, Option<V>)>;
+
+    // merges with `side` as the answer to every conflict, returning the changes and the conflicts
+    fn merged<V: PartialEq + Clone>(
+        ours: &IndexMap<String, V>,
+        theirs: &IndexMap<String, V>,
+        base: Option<&IndexMap<String, V>>,
+        side: Side,
+    ) -> (Changes<V>, Vec<String>) {
+        let mut conflicts = vec![];
+        let changes = merge_maps(ours, theirs, base, |k| k.to_string(), &mut |conflict| {
+            conflicts.push(format!(
+                "{}: {}/{}",
+                conflict.path, conflict.ours, conflict.theirs
+            ));
+            Ok(side)
+        })
+        .unwrap();
+
+        (changes, conflicts)
+    }
+
+    fn take(key: &str, value: Option<i32>) -> (String, Option<i32>) {
+        (key.to_string(), value)
+    }
+
+    #[test]
+    fn merge_with_base_takes_changes_of_one_side() {
+        let base = map(&[("changed", 1), ("removed", 1), ("kept", 1)]);
+        let ours = map(&[("changed", 1), ("removed", 1), ("kept", 1)]);
+        let theirs = map(&[("changed", 2), ("kept", 1), ("added", 1)]);
+
+        let (changes, conflicts) = merged(&ours, &theirs, Some(&base), Side::Ours);
+
+        assert_eq!(
+            changes,
+            [
+                take("changed", Some(2)),
+                take("removed", None),
+                take("added", Some(1))
+            ]
+        );
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_with_base_keeps_changes_here() {
+        let base = map(&[("changed", 1), ("removed", 1)]);
+        let ours = map(&[("changed", 2), ("added", 1)]);
+        let theirs = map(&[("changed", 1), ("removed", 1)]);
+
+        let (changes, conflicts) = merged(&ours, &theirs, Some(&base), Side::Theirs);
+
+        assert!(changes.is_empty());
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_with_base_same_change_on_both_sides() {
+        let base = map(&[("changed", 1), ("removed", 1)]);
+        let ours = map(&[("changed", 2), ("added", 1)]);
+
+        let (changes, conflicts) = merged(&ours, &ours.clone(), Some(&base), Side::Theirs);
+
+        assert!(changes.is_empty());
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_with_base_conflicts() {
+        let base = map(&[("changed", 1), ("removed", 1)]);
+        let ours = map(&[("changed", 2), ("removed", 2), ("added", 1)]);
+        let theirs = map(&[("changed", 3), ("added", 2)]);
+
+        let (changes, conflicts) = merged(&ours, &theirs, Some(&base), Side::Ours);
+        assert!(changes.is_empty());
+        assert_eq!(
+            conflicts,
+            [
+                "changed: changed/changed",
+                "removed: changed/removed",
+                "added: added/added"
+            ]
+        );
+
+        let (changes, _) = merged(&ours, &theirs, Some(&base), Side::Theirs);
+        assert_eq!(
+            changes,
+            [
+                take("changed", Some(3)),
+                take("removed", None),
+                take("added", Some(2))
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_without_base_adds_and_keeps() {
+        let ours = map(&[("same", 1), ("only_here", 1)]);
+        let theirs = map(&[("same", 1), ("only_there", 1)]);
+
+        let (changes, conflicts) = merged(&ours, &theirs, None, Side::Theirs);
+
+        assert_eq!(changes, [take("only_there", Some(1))]);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn merge_without_base_conflicts_on_every_difference() {
+        let ours = map(&[("a", 1), ("b", 1)]);
+        let theirs = map(&[("a", 2), ("b", 2)]);
+
+        let (changes, conflicts) = merged(&ours, &theirs, None, Side::Theirs);
+
+        assert_eq!(changes, [take("a", Some(2)), take("b", Some(2))]);
+        assert_eq!(conflicts, ["a: changed/changed", "b: changed/changed"]);
+    }
+
+    #[test]
+    fn merge_pairs_logins_with_their_salts() {
+        let (_dir, state) = store(&["a", "b"]);
+        let mut other_items = state.login_items();
+        // the same entry under another salt can't be decrypted with it, so it is a change too
+        other_items["a"].1.as_mut().unwrap().salt = generate_salt();
+        other_items["b"].1 = None;
+
+        let (changes, conflicts) = merged(
+            &state.login_items(),
+            &other_items,
+            Some(&state.login_items()),
+            Side::Ours,
+        );
+        assert_eq!(
+            changes.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+        assert!(conflicts.is_empty());
+
+        let (changes, conflicts) = merged(&state.login_items(), &other_items, None, Side::Ours);
+        assert!(changes.is_empty());
+        assert_eq!(conflicts, ["a: changed/changed", "b: changed/changed"]);
+    }
+
+    #[test]
+    fn add_many_renames_reserved_fields() {
+        let (dir, mut state) = store(&[]);
+        let mut login = UnencryptedLogin::new("a".to_string(), Zeroizing::new("pw".to_string()));
+        for (key, value) in [("password", "1"), ("created", "2"), ("field_created", "3")] {
+            login
+                .extra_fields
+                .insert(key.to_string(), value.to_string());
+        }
+        login
+            .secret_fields
+            .insert("modified".to_string(), Zeroizing::new("4".to_string()));
+
+        state
+            .add_many(vec![login], master(), &mut |_, _| true)
+            .unwrap();
+        state.save("Add a").unwrap();
+        drop(state);
+
+        let state = State::load(dir.path()).unwrap();
+        let fields: Vec<_> = state
+            .fields("a")
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
             .collect();
+        assert_eq!(
+            fields,
+            [
+                ("field_created", "3"),
+                ("field_password", "1"),
+                ("field_field_created", "2")
+            ]
+        );
+        assert_eq!(state.secret_fields("a").unwrap(), &["field_modified"]);
+        assert_eq!(
+            state
+                .get_secret("a".to_string(), "field_modified", master())
+                .unwrap()
+                .as_str(),
+            "4"
+        );
+        assert_ne!(state.history("a").unwrap().0.unwrap(), "2");
+    }
 
-        let count = to_add.len();
-        self.add_many(to_add, master_password)?;
+    #[test]
+    fn legacy_fields_are_renamed_on_load_and_migrate() {
+        let (dir, state) = store(&[]);
+        drop(state);
+        let info = StoreInfo {
+            version: 3,
+            ..Default::default()
+        };
+        save_to_file(&dir.path().join("store.yml"), &info).unwrap();
+        fs::write(
+            dir.path().join("logins.yml"),
+            "web:
+  a:
+    password: pw
+    created: last year
+    modified: secret
+    password_history: none
+    field_created: taken
+    secret_fields:
+    - modified
+  password:
+    password: pw
+",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("salts.yml"),
+            "web/a:
+  salt: s
+  nonce: n
+  fields:
+    modified:
+      salt: fs
+      nonce: fn
+",
+        )
+        .unwrap();
 
-        Ok(count)
+        let check = |state: &State| {
+            assert_eq!(paths(state), ["web/a", "web/password"]);
+            assert_eq!(state.history("web/a").unwrap(), (None, None, vec![]));
+            let fields: Vec<_> = state
+                .fields("web/a")
+                .unwrap()
+                .into_iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect();
+            assert_eq!(
+                fields,
+                [
+                    ("field_created", "taken"),
+                    ("field_field_created", "last year"),
+                    ("field_password_history", "none")
+                ]
+            );
+            assert_eq!(state.secret_fields("web/a").unwrap(), &["field_modified"]);
+            assert!(state.salts["web/a"].fields.contains_key("field_modified"));
+        };
+
+        check(&State::load(dir.path()).unwrap());
+
+        let mut state = State::load_mut(dir.path()).unwrap();
+        state.migrate();
+        state.save("Migrate").unwrap();
+        drop(state);
+        assert_eq!(saved_version(dir.path()), FORMAT_VERSION);
+        check(&State::load(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn hidden_maps_keep_timestamps() {
+        let (_dir, mut state) = store(&["bank/a"]);
+        let created = state.history("bank/a").unwrap().0.cloned();
+        assert!(created.is_some());
+
+        state.hide("bank".to_string(), master()).unwrap();
+        state.unhide("bank".to_string(), master()).unwrap();
+
+        assert_eq!(state.history("bank/a").unwrap().0.cloned(), created);
+    }
+
+    #[test]
+    fn legacy_fields_of_hidden_maps_are_renamed() {
+        let (_dir, mut state) = store(&["bank/a"]);
+        state.hide("bank".to_string(), master()).unwrap();
+
+        // rewrite the map as hidden before format version 4, without a version or timestamps
+        let mut keys = state.keys(MASTER);
+        let hidden = &state.hidden_logins["bank"];
+        let yaml = decrypt_secret(&hidden.logins, &hidden.salt, &mut keys).unwrap();
+        let mut raw: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        raw.as_mapping_mut().unwrap().remove("version");
+        let entry = &mut raw["logins"]["bank/a"];
+        let login = entry["login"].as_mapping_mut().unwrap();
+        for (key, value) in [
+            ("created", "last year"),
+            ("modified", "ciphertext"),
+            ("password_history", "none"),
+        ] {
+            login.insert(key.into(), value.into());
+        }
+        login.insert("secret_fields".into(), vec!["modified"].into());
+        entry["salt"]["fields"] = serde_yaml::from_str("modified: {salt: s, nonce: n}").unwrap();
+        let (ciphertext, salt) =
+            encrypt_secret(&serde_yaml::to_string(&raw).unwrap(), &mut keys).unwrap();
+        drop(keys);
+        state.hidden_logins.insert(
+            "bank".to_string(),
+            HiddenMap {
+                logins: ciphertext,
+                salt,
+            },
+        );
+
+        let get = |field| state.get_hidden("bank/a".to_string(), field, master(), master());
+        assert_eq!(&*get("password").unwrap(), "pw");
+
+        state.unhide("bank".to_string(), master()).unwrap();
+        assert_eq!(state.history("bank/a").unwrap(), (None, None, vec![]));
+        let fields: Vec<_> = state
+            .fields("bank/a")
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("field_created", "last year"),
+                ("field_password_history", "none")
+            ]
+        );
+        assert_eq!(state.secret_fields("bank/a").unwrap(), &["field_modified"]);
+        assert!(state.salts["bank/a"].fields.contains_key("field_modified"));
+    }
+
+    // a store whose last save, adding "b", crashed after staging its files, before or after the
+    // journal was written
+    fn crashed_save(journal: bool) -> tempfile::TempDir {
+        let (dir, mut state) = store(&["a"]);
+        state.save("Add a").unwrap();
+        state.add("b".to_string(), master(), master()).unwrap();
+
+        stage_file(&dir.path().join("logins.yml"), "new", &state.logins).unwrap();
+        stage_file(&dir.path().join("salts.yml"), "new", &state.salts).unwrap();
+        if journal {
+            save_to_file(&dir.path().join(JOURNAL_FILE), &["logins.yml", "salts.yml"]).unwrap();
+        }
+
+        dir
+    }
+
+    fn leftovers(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".new") || name == JOURNAL_FILE)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn committed_save_is_rolled_forward() {
+        let dir = crashed_save(true);
+        assert_eq!(
+            leftovers(dir.path()),
+            ["logins.yml.new", "salts.yml.new", JOURNAL_FILE]
+        );
+
+        let state = State::load_mut(dir.path()).unwrap();
+
+        assert_eq!(paths(&state), ["a", "b"]);
+        assert!(state.salts.contains_key("b"));
+        assert!(leftovers(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn partly_renamed_save_is_rolled_forward() {
+        let dir = crashed_save(true);
+        fs::rename(
+            dir.path().join("logins.yml.new"),
+            dir.path().join("logins.yml"),
+        )
+        .unwrap();
+
+        let state = State::load_mut(dir.path()).unwrap();
+
+        assert_eq!(paths(&state), ["a", "b"]);
+        assert!(state.salts.contains_key("b"));
+        assert!(leftovers(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn uncommitted_save_is_discarded() {
+        let dir = crashed_save(false);
+
+        let state = State::load_mut(dir.path()).unwrap();
+
+        assert_eq!(paths(&state), ["a"]);
+        assert!(!state.salts.contains_key("b"));
+        assert!(leftovers(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn read_only_open_recovers_first() {
+        let committed = crashed_save(true);
+        let discarded = crashed_save(false);
+
+        let state = State::load(committed.path()).unwrap();
+        assert_eq!(paths(&state), ["a", "b"]);
+        assert!(leftovers(committed.path()).is_empty());
+        assert_eq!(paths(&State::load(discarded.path()).unwrap()), ["a"]);
+        assert!(leftovers(discarded.path()).is_empty());
+
+        // the recovery lock is released again, only a shared lock is held while reading
+        assert!(State::load(committed.path()).is_ok());
+        assert!(lock_dir(committed.path(), false, Duration::ZERO).is_ok());
+        assert!(lock_dir(committed.path(), true, Duration::ZERO).is_err());
+        drop(state);
+        assert!(lock_dir(committed.path(), true, Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn read_only_open_waits_for_writers_to_recover() {
+        let dir = crashed_save(true);
+        let _reader = lock_dir(dir.path(), false, Duration::ZERO).unwrap();
+
+        // recovery needs the exclusive lock, so it must not happen under another reader's lock
+        let state = State::open(dir.path(), false, Duration::ZERO);
+
+        assert!(state.is_err());
+        assert_eq!(leftovers(dir.path()).len(), 3);
+    }
+
+    fn new_master() -> Zeroizing<String> {
+        Zeroizing::new("new master".to_string())
+    }
+
+    #[test]
+    fn rekey_changes_master_password() {
+        let (dir, mut state) = store(&["a", "b/c"]);
+
+        let (count, failed) = state
+            .rekey("/".to_string(), master(), new_master(), &mut |_, _| true)
+            .unwrap();
+        state.save("Rekey").unwrap();
+        drop(state);
+
+        assert_eq!((count, failed.len()), (2, 0));
+        let state = State::load(dir.path()).unwrap();
+        assert_eq!(&*state.get("b/c".to_string(), new_master()).unwrap(), "pw");
+        assert!(state.get("a".to_string(), master()).is_err());
+    }
+
+    #[test]
+    fn rekey_only_touches_entries_under_path() {
+        let (_dir, mut state) = store(&["a/x", "b"]);
+
+        let (count, _) = state
+            .rekey("a".to_string(), master(), new_master(), &mut |_, _| true)
+            .unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(&*state.get("a/x".to_string(), new_master()).unwrap(), "pw");
+        assert_eq!(&*state.get("b".to_string(), master()).unwrap(), "pw");
+    }
+
+    #[test]
+    fn rekey_under_hidden_root_keeps_map_key() {
+        let (_dir, mut state) = store(&["bank/x", "bank/y"]);
+        state.hide("bank".to_string(), master()).unwrap();
+
+        let (count, failed) = state
+            .rekey("bank/x".to_string(), master(), new_master(), &mut |_, _| {
+                true
+            })
+            .unwrap();
+
+        assert_eq!((count, failed.len()), (1, 0));
+        let get = |path: &str, unhide, master| {
+            state.get_hidden(path.to_string(), "password", unhide, master)
+        };
+        assert_eq!(&*get("bank/x", master(), new_master()).unwrap(), "pw");
+        assert_eq!(&*get("bank/y", master(), master()).unwrap(), "pw");
+        assert!(get("bank/x", new_master(), new_master()).is_err());
+    }
+
+    #[test]
+    fn rekey_of_whole_hidden_root_changes_map_key() {
+        let (_dir, mut state) = store(&["bank/x"]);
+        state.hide("bank".to_string(), master()).unwrap();
+
+        let (count, _) = state
+            .rekey("/".to_string(), master(), new_master(), &mut |_, _| true)
+            .unwrap();
+
+        assert_eq!(count, 1);
+        state.unhide("bank".to_string(), new_master()).unwrap();
+        assert_eq!(
+            &*state.get("bank/x".to_string(), new_master()).unwrap(),
+            "pw"
+        );
+    }
+
+    #[test]
+    fn rekey_reports_failures_by_hidden_root() {
+        let other = || Zeroizing::new("other".to_string());
+        let (_dir, mut state) = store(&["a", "bank/y"]);
+        state
+            .add("b".to_string(), Zeroizing::new("pw".to_string()), other())
+            .unwrap();
+        state
+            .add(
+                "bank/x".to_string(),
+                Zeroizing::new("pw".to_string()),
+                other(),
+            )
+            .unwrap();
+        state.hide("bank".to_string(), master()).unwrap();
+
+        let (count, failed) = state
+            .rekey("/".to_string(), master(), new_master(), &mut |_, _| true)
+            .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(failed, ["b", "bank"]);
+        assert_eq!(&*state.get("b".to_string(), other()).unwrap(), "pw");
+        let get = |path: &str, master| {
+            state.get_hidden(path.to_string(), "password", new_master(), master)
+        };
+        assert_eq!(&*get("bank/y", new_master()).unwrap(), "pw");
+        assert_eq!(&*get("bank/x", other()).unwrap(), "pw");
+    }
+
+    #[test]
+    fn audit_decrypts_only_passwords() {
+        let (_dir, mut state) = store(&["a"]);
+        let secret = || Zeroizing::new("secret".to_string());
+        state
+            .set_secret_field("a", "pin".to_string(), secret(), master())
+            .unwrap();
+        state
+            .add("a".to_string(), Zeroizing::new("new".to_string()), master())
+            .unwrap();
+        let other = Zeroizing::new("other".to_string());
+        state.add("b".to_string(), secret(), other).unwrap();
+
+        let (logins, failed) = state.audit_logins("/", master(), &mut |_, _| true).unwrap();
+
+        assert_eq!(failed, ["b"]);
+        let (created, _, replaced) = state.history("a").unwrap();
+        assert_eq!(logins[0].password.as_str(), "new");
+        assert!(logins[0].secret_fields.is_empty());
+        assert_eq!(logins[0].created.as_ref(), created);
+        assert_eq!(logins[0].history.len(), 1);
+        assert_eq!(&logins[0].history[0].1, replaced[0]);
+        assert!(logins[0].history[0].0.is_empty());
+
+        let (logins, _) = state.export("/", master(), &mut |_, _| true).unwrap();
+        assert_eq!(logins[0].secret_fields["pin"].as_str(), "secret");
+        assert_eq!(logins[0].history[0].0.as_str(), "pw");
     }
 }
@@ -0,0 +1,323 @@
+use serde::Serialize;
+use std::{collections::HashMap, time::SystemTime};
+
+use crate::login::UnencryptedLogin;
+
+// the most common leaked passwords, scored as if guessed from this list
+const COMMON: &[&str] = &[
+    "123456",
+    "password",
+    "12345678",
+    "qwerty",
+    "123456789",
+    "12345",
+    "1234",
+    "111111",
+    "1234567",
+    "dragon",
+    "123123",
+    "baseball",
+    "abc123",
+    "football",
+    "monkey",
+    "letmein",
+    "696969",
+    "shadow",
+    "master",
+    "666666",
+    "qwertyuiop",
+    "123321",
+    "mustang",
+    "1234567890",
+    "michael",
+    "654321",
+    "superman",
+    "1qaz2wsx",
+    "7777777",
+    "121212",
+    "000000",
+    "qazwsx",
+    "123qwe",
+    "killer",
+    "trustno1",
+    "jordan",
+    "jennifer",
+    "zxcvbnm",
+    "asdfgh",
+    "hunter",
+    "buster",
+    "soccer",
+    "harley",
+    "batman",
+    "andrew",
+    "tigger",
+    "sunshine",
+    "iloveyou",
+    "welcome",
+    "admin",
+    "passw0rd",
+    "password1",
+    "princess",
+    "starwars",
+    "login",
+    "secret",
+];
+
+// zxcvbn's scores, 0 (too guessable) to 4 (very unguessable), by estimated bits of entropy
+const SCORES: [(f64, &str); 5] = [
+    (0.0, "very weak"),
+    (28.0, "weak"),
+    (36.0, "fair"),
+    (60.0, "good"),
+    (80.0, "strong"),
+];
+
+#[derive(Serialize)]
+pub struct LoginReport {
+    pub path: String,
+    pub length: usize,
+    pub entropy: f64,
+    pub score: usize,
+    pub strength: &'static str,
+    // when the password was set, from its history or the creation time
+    pub changed: Option<String>,
+    pub age_days: Option<u64>,
+    // the other logins sharing this password
+    pub reused_by: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub logins: Vec<LoginReport>,
+    pub reused: Vec<Vec<String>>,
+}
+
+pub fn audit(logins: &[UnencryptedLogin]) -> Report {
+    let mut groups: HashMap<&str, Vec<&str>> = HashMap::new();
+    for login in logins {
+        groups
+            .entry(login.password.as_str())
+            .or_default()
+            .push(&login.login_name);
+    }
+
+    let reports = logins
+        .iter()
+        .map(|login| {
+            let entropy = entropy(&login.password);
+            let score = score(entropy);
+            let changed = login
+                .history
+                .first()
+                .map(|(_, replaced)| replaced)
+                .or(login.created.as_ref())
+                .cloned();
+
+            LoginReport {
+                path: login.login_name.clone(),
+                length: login.password.chars().count(),
+                entropy: (entropy * 10.0).round() / 10.0,
+                score,
+                strength: SCORES[score].1,
+                age_days: changed.as_deref().and_then(age_days),
+                changed,
+                reused_by: groups[login.password.as_str()]
+                    .iter()
+                    .filter(|name| **name != login.login_name)
+                    .map(|name| name.to_string())
+                    .collect(),
+            }
+        })
+        .collect();
+
+    let mut reused: Vec<Vec<String>> = groups
+        .into_values()
+        .filter(|names| names.len() > 1)
+        .map(|names| names.into_iter().map(String::from).collect())
+        .collect();
+    reused.sort();
+
+    Report {
+        logins: reports,
+        reused,
+    }
+}
+
+// A rough estimate in the spirit of zxcvbn: every character adds the bits of the character
+// classes used, except repeats and sequences ("aaa", "abc", "321"), which add one bit each.
+// Common passwords only get the bits needed to pick them from the list.
+fn entropy(password: &str) -> f64 {
+    let lowercase = password.to_lowercase();
+    if let Some(rank) = COMMON.iter().position(|common| *common == lowercase) {
+        return ((rank + 2) as f64).log2();
+    }
+
+    let mut pool = 0;
+    if password.chars().any(|c| c.is_ascii_lowercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_uppercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_digit()) {
+        pool += 10;
+    }
+    if password
+        .chars()
+        .any(|c| c.is_ascii_punctuation() || c == ' ')
+    {
+        pool += 33;
+    }
+    if !password.is_ascii() {
+        pool += 100;
+    }
+    let bits_per_char = (pool.max(1) as f64).log2();
+
+    let mut bits = 0.0;
+    let mut previous: Option<char> = None;
+    for c in password.chars() {
+        let predictable = previous.is_some_and(|p| (c as i64 - p as i64).abs() <= 1);
+        bits += if predictable { 1.0 } else { bits_per_char };
+        previous = Some(c);
+    }
+
+    bits
+}
+
+fn score(entropy: f64) -> usize {
+    SCORES
+        .iter()
+        .rposition(|(bits, _)| entropy >= *bits)
+        .unwrap_or(0)
+}
+
+fn age_days(timestamp: &str) -> Option<u64> {
+    let time = humantime::parse_rfc3339(timestamp).ok()?;
+    let age = SystemTime::now().duration_since(time).ok()?;
+    Some(age.as_secs() / 86400)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use zeroize::Zeroizing;
+
+    use super::*;
+
+    fn login(path: &str, password: &str) -> UnencryptedLogin {
+        UnencryptedLogin::new(path.to_string(), Zeroizing::new(password.to_string()))
+    }
+
+    fn days_ago(days: u64) -> String {
+        let time = SystemTime::now() - Duration::from_secs(days * 86400 + 60);
+        humantime::format_rfc3339_seconds(time).to_string()
+    }
+
+    fn assert_bits(password: &str, bits: f64) {
+        let actual = entropy(password);
+        assert!((actual - bits).abs() < 1e-9, "{}: {}", password, actual);
+    }
+
+    #[test]
+    fn common_passwords_by_rank() {
+        assert_bits("123456", 1.0);
+        assert_bits("password", 3f64.log2());
+        assert_bits("PassWord", 3f64.log2());
+        assert_bits("secret", ((COMMON.len() + 1) as f64).log2());
+    }
+
+    #[test]
+    fn character_classes() {
+        assert_bits("", 0.0);
+        assert_bits("x", 26f64.log2());
+        assert_bits("xQ", 2.0 * 52f64.log2());
+        assert_bits("x7", 2.0 * 36f64.log2());
+        assert_bits("x!", 2.0 * 59f64.log2());
+        assert_bits("x Q7", 4.0 * 95f64.log2());
+    }
+
+    #[test]
+    fn sequences_and_repeats_add_one_bit() {
+        let first = 26f64.log2();
+        assert_bits("aaaaaa", first + 5.0);
+        assert_bits("abcdef", first + 5.0);
+        assert_bits("fedcba", first + 5.0);
+        assert_bits("acegik", 6.0 * first);
+        assert_bits("xabcx", 3.0 * first + 2.0);
+        assert_bits("987", 10f64.log2() + 2.0);
+    }
+
+    #[test]
+    fn non_ascii_characters() {
+        assert_bits("äöü", 3.0 * 100f64.log2());
+        assert_bits("aä", 2.0 * 126f64.log2());
+        assert_bits("日本語", 3.0 * 100f64.log2());
+    }
+
+    #[test]
+    fn score_thresholds() {
+        let scores: Vec<_> = [0.0, 27.9, 28.0, 35.9, 36.0, 59.9, 60.0, 79.9, 80.0, 200.0]
+            .into_iter()
+            .map(score)
+            .collect();
+
+        assert_eq!(scores, [0, 0, 1, 1, 2, 2, 3, 3, 4, 4]);
+    }
+
+    #[test]
+    fn report_scores_logins() {
+        let report = audit(&[
+            login("weak", "password"),
+            login("strong", "x Q7!kR2v#Lm9$Tz"),
+        ]);
+
+        assert_eq!(report.logins[0].strength, "very weak");
+        assert_eq!(report.logins[0].length, 8);
+        assert_eq!(report.logins[0].entropy, 1.6);
+        assert_eq!(report.logins[1].strength, "strong");
+    }
+
+    #[test]
+    fn reused_passwords_are_grouped() {
+        let report = audit(&[
+            login("a", "one"),
+            login("b", "two"),
+            login("c", "one"),
+            login("d", "three"),
+            login("e", "two"),
+            login("f", "one"),
+        ]);
+
+        assert_eq!(report.reused, [vec!["a", "c", "f"], vec!["b", "e"]]);
+        let reused_by: Vec<_> = report.logins.iter().map(|l| l.reused_by.clone()).collect();
+        assert_eq!(reused_by[0], ["c", "f"]);
+        assert_eq!(reused_by[1], ["e"]);
+        assert!(reused_by[3].is_empty());
+    }
+
+    #[test]
+    fn age_from_history_or_creation() {
+        let mut created = login("created", "pw");
+        created.created = Some(days_ago(30));
+        let mut changed = login("changed", "pw2");
+        changed.created = Some(days_ago(30));
+        let replaced = days_ago(3);
+        changed.history = vec![(Zeroizing::new("old".to_string()), replaced.clone())];
+        let unknown = login("unknown", "pw3");
+
+        let report = audit(&[created, changed, unknown]);
+
+        let ages: Vec<_> = report.logins.iter().map(|l| l.age_days).collect();
+        assert_eq!(ages, [Some(30), Some(3), None]);
+        assert_eq!(report.logins[1].changed, Some(replaced));
+    }
+
+    #[test]
+    fn age_days_of_timestamps() {
+        assert_eq!(age_days(&days_ago(0)), Some(0));
+        assert_eq!(age_days(&days_ago(400)), Some(400));
+        assert_eq!(age_days("2999-01-01T00:00:00Z"), None);
+        assert_eq!(age_days("yesterday"), None);
+    }
+}
@@ -0,0 +1,310 @@
+use std::{thread, time::Duration};
+
+use anyhow::{anyhow, bail};
+use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+use zeroize::Zeroizing;
+
+use crate::{
+    api::State,
+    config::{TypeConfig, TypeMode},
+    totp::Totp,
+};
+
+const DEFAULT_SEQUENCE: &str = "{password}";
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    Field(String),
+    Key(Key),
+    Delay(Duration),
+}
+
+#[derive(Clone)]
+pub enum Action {
+    Text(Zeroizing<String>),
+    Key(Key),
+    Delay(Duration),
+    Otp(Totp),
+}
+
+impl Action {
+    pub fn uses_otp(actions: &[Action]) -> bool {
+        actions.iter().any(|a| matches!(a, Action::Otp(_)))
+    }
+}
+
+// e.g. "{username}{TAB}{password}{DELAY 500}{ENTER}", with "{{" and "}}" for literal braces
+fn parse(sequence: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut text = String::new();
+    let mut chars = sequence.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => bail!("Unterminated '{{' in auto-type sequence"),
+                    }
+                }
+
+                if !text.is_empty() {
+                    tokens.push(Token::Text(std::mem::take(&mut text)));
+                }
+                tokens.push(parse_placeholder(&placeholder)?);
+            }
+            '}' => bail!("Unmatched '}}' in auto-type sequence"),
+            _ => text.push(c),
+        }
+    }
+
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+
+    Ok(tokens)
+}
+
+fn parse_placeholder(placeholder: &str) -> anyhow::Result<Token> {
+    if let Some(ms) = placeholder.strip_prefix("DELAY ") {
+        let ms = ms
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("Invalid delay '{}' in auto-type sequence", ms))?;
+        return Ok(Token::Delay(Duration::from_millis(ms)));
+    }
+
+    let key = match placeholder {
+        "TAB" => Key::Tab,
+        "ENTER" => Key::Return,
+        "SPACE" => Key::Space,
+        "BACKSPACE" => Key::Backspace,
+        "DELETE" => Key::Delete,
+        "ESC" => Key::Escape,
+        "UP" => Key::UpArrow,
+        "DOWN" => Key::DownArrow,
+        "LEFT" => Key::LeftArrow,
+        "RIGHT" => Key::RightArrow,
+        "HOME" => Key::Home,
+        "END" => Key::End,
+        "" => bail!("Empty placeholder in auto-type sequence"),
+        field => return Ok(Token::Field(field.to_string())),
+    };
+
+    Ok(Token::Key(key))
+}
+
+// The sequence is taken from the argument, the login's `autotype` field, or the default, in this order
+pub fn resolve(
+    state: &State,
+    login_name: &str,
+    sequence: Option<&str>,
+    master_password: Zeroizing<String>,
+) -> anyhow::Result<Vec<Action>> {
+    let fields = state.fields(login_name)?;
+    let secret_fields = state.secret_fields(login_name)?;
+
+    let sequence = sequence
+        .or_else(|| {
+            fields
+                .iter()
+                .find(|(k, _)| *k == "autotype")
+                .map(|(_, v)| v.as_str())
+        })
+        .unwrap_or(DEFAULT_SEQUENCE);
+
+    let mut actions = vec![];
+    for token in parse(sequence)? {
+        actions.push(match token {
+            Token::Text(text) => Action::Text(Zeroizing::new(text)),
+            Token::Key(key) => Action::Key(key),
+            Token::Delay(delay) => Action::Delay(delay),
+            Token::Field(field) if field == "password" || secret_fields.contains(&field) => {
+                let secret =
+                    state.get_secret(login_name.to_string(), &field, master_password.clone())?;
+
+                if field == "otp" {
+                    Action::Otp(Totp::parse(&secret)?)
+                } else {
+                    Action::Text(secret)
+                }
+            }
+            Token::Field(field) => {
+                let value = fields
+                    .iter()
+                    .find(|(k, _)| **k == field)
+                    .map(|(_, v)| v.to_string())
+                    .ok_or_else(|| anyhow!("Field '{}' not found in '{}'", field, login_name))?;
+
+                Action::Text(Zeroizing::new(value))
+            }
+        });
+    }
+
+    Ok(actions)
+}
+
+pub fn type_actions(actions: &[Action], config: &TypeConfig) -> anyhow::Result<()> {
+    let mut enigo = Enigo::new(&Settings::default())?;
+    let key_delay = Duration::from_millis(config.key_delay_ms);
+
+    for action in actions {
+        match action {
+            Action::Text(text) => type_text(&mut enigo, text, config)?,
+            Action::Key(key) => enigo.key(*key, Direction::Click)?,
+            Action::Delay(delay) => thread::sleep(*delay),
+            Action::Otp(totp) => type_text(&mut enigo, &totp.now()?, config)?,
+        }
+
+        thread::sleep(key_delay);
+    }
+
+    Ok(())
+}
+
+fn type_text(enigo: &mut Enigo, text: &str, config: &TypeConfig) -> anyhow::Result<()> {
+    match config.mode {
+        TypeMode::Text => enigo.text(text)?,
+        TypeMode::Characters => {
+            for c in text.chars() {
+                enigo.key(Key::Unicode(c), Direction::Click)?;
+                thread::sleep(Duration::from_millis(config.key_delay_ms));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::KdfParams;
+
+    use super::*;
+
+    fn text(text: &str) -> Token {
+        Token::Text(text.to_string())
+    }
+
+    fn field(name: &str) -> Token {
+        Token::Field(name.to_string())
+    }
+
+    fn error(sequence: &str) -> String {
+        parse(sequence).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn keys_and_fields() {
+        assert_eq!(
+            parse("{username}{TAB}{password}{ENTER}").unwrap(),
+            [
+                field("username"),
+                Token::Key(Key::Tab),
+                field("password"),
+                Token::Key(Key::Return)
+            ]
+        );
+    }
+
+    #[test]
+    fn text_between_placeholders() {
+        assert_eq!(
+            parse("user {username}!{SPACE}end").unwrap(),
+            [
+                text("user "),
+                field("username"),
+                text("!"),
+                Token::Key(Key::Space),
+                text("end")
+            ]
+        );
+        assert!(parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn delays() {
+        assert_eq!(
+            parse("{DELAY 500}{DELAY  20 }").unwrap(),
+            [
+                Token::Delay(Duration::from_millis(500)),
+                Token::Delay(Duration::from_millis(20))
+            ]
+        );
+        assert_eq!(
+            error("{DELAY soon}"),
+            "Invalid delay 'soon' in auto-type sequence"
+        );
+        assert!(parse("{DELAY -1}").is_err());
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!(
+            parse("{{a}}{b}}}").unwrap(),
+            [text("{a}"), field("b"), text("}")]
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_fields() {
+        assert_eq!(
+            parse("{F13}{tab}{otp}").unwrap(),
+            [field("F13"), field("tab"), field("otp")]
+        );
+    }
+
+    #[test]
+    fn unbalanced_braces() {
+        assert_eq!(error("{username"), "Unterminated '{' in auto-type sequence");
+        assert_eq!(error("a}b"), "Unmatched '}' in auto-type sequence");
+        assert_eq!(error("{}"), "Empty placeholder in auto-type sequence");
+    }
+
+    #[test]
+    fn resolve_plain_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        State::init(dir.path()).unwrap();
+        let mut state = State::load_mut(dir.path()).unwrap();
+        state
+            .set_kdf(KdfParams {
+                m_cost: 8,
+                t_cost: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        let master = || Zeroizing::new("master".to_string());
+        state
+            .add("a".to_string(), Zeroizing::new("pw".to_string()), master())
+            .unwrap();
+        state
+            .set_field("a", "username".to_string(), "alice".to_string())
+            .unwrap();
+
+        let actions = resolve(&state, "a", Some("{username}{TAB}{password}"), master()).unwrap();
+        let typed: Vec<_> = actions
+            .iter()
+            .map(|action| match action {
+                Action::Text(text) => text.to_string(),
+                Action::Key(key) => format!("{:?}", key),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(typed, ["alice", "Tab", "pw"]);
+
+        let error = resolve(&state, "a", Some("{F13}"), master()).err().unwrap();
+        assert_eq!(error.to_string(), "Field 'F13' not found in 'a'");
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{anyhow, bail};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as b64, Engine as _};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::crypto::{decrypt, derive_key, encrypt, generate_salt, Cipher, KdfParams};
+
+const ARCHIVE_FORMAT: &str = "qass-backup";
+const ARCHIVE_VERSION: u32 = 1;
+
+// The whole store is serialized and encrypted as one blob under a key derived from the backup
+// passphrase, so logins, salts and hidden paths can't be told apart in the archive.
+#[derive(Serialize, Deserialize)]
+pub struct Archive {
+    format: String,
+    version: u32,
+    kdf: KdfParams,
+    cipher: Cipher,
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+impl Archive {
+    pub fn seal<T: Serialize>(
+        contents: &T,
+        passphrase: &str,
+        kdf: KdfParams,
+    ) -> anyhow::Result<Archive> {
+        let cleartext = Zeroizing::new(serde_yaml::to_string(contents)?);
+
+        let salt = generate_salt();
+        let key = Zeroizing::new(derive_key(passphrase, &salt, &kdf)?);
+        let cipher = Cipher::default();
+        let (nonce, ciphertext) = encrypt(&cleartext, &key, cipher)?;
+
+        Ok(Archive {
+            format: ARCHIVE_FORMAT.to_string(),
+            version: ARCHIVE_VERSION,
+            kdf,
+            cipher,
+            salt,
+            nonce: b64.encode(nonce),
+            data: b64.encode(ciphertext),
+        })
+    }
+
+    pub fn open<T: DeserializeOwned>(&self, passphrase: &str) -> anyhow::Result<T> {
+        if self.format != ARCHIVE_FORMAT {
+            bail!("Not a qass backup archive");
+        }
+        if self.version > ARCHIVE_VERSION {
+            bail!(
+                "Backup archive version {} is newer than this version of qass supports ({})",
+                self.version,
+                ARCHIVE_VERSION
+            );
+        }
+        self.kdf.validate()?;
+
+        let key = Zeroizing::new(derive_key(passphrase, &self.salt, &self.kdf)?);
+        // the cipher authenticates the data, so any modification fails here too
+        let cleartext = Zeroizing::new(
+            decrypt(
+                &b64.decode(&self.data)?,
+                &key,
+                &b64.decode(&self.nonce)?,
+                self.cipher,
+            )
+            .map_err(|_| anyhow!("Wrong passphrase or corrupted backup archive"))?,
+        );
+
+        Ok(serde_yaml::from_str(&cleartext)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    type Contents = BTreeMap<String, String>;
+
+    fn kdf() -> KdfParams {
+        KdfParams {
+            m_cost: 8,
+            t_cost: 1,
+            ..Default::default()
+        }
+    }
+
+    fn contents() -> Contents {
+        [("a/b", "c"), ("d", "e")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn sealed() -> Archive {
+        Archive::seal(&contents(), "passphrase", kdf()).unwrap()
+    }
+
+    fn error(archive: &Archive, passphrase: &str) -> String {
+        archive
+            .open::<Contents>(passphrase)
+            .err()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn round_trip() {
+        let archive = sealed();
+
+        assert_eq!(archive.open::<Contents>("passphrase").unwrap(), contents());
+        assert!(!archive.data.contains("a/b"));
+    }
+
+    #[test]
+    fn round_trip_through_yaml() {
+        let yaml = serde_yaml::to_string(&sealed()).unwrap();
+        let archive: Archive = serde_yaml::from_str(&yaml).unwrap();
+
+        assert_eq!(archive.open::<Contents>("passphrase").unwrap(), contents());
+    }
+
+    #[test]
+    fn salt_and_nonce_are_fresh() {
+        let (a, b) = (sealed(), sealed());
+
+        assert_ne!(a.salt, b.salt);
+        assert_ne!(a.nonce, b.nonce);
+        assert_ne!(a.data, b.data);
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        assert_eq!(
+            error(&sealed(), "wrong"),
+            "Wrong passphrase or corrupted backup archive"
+        );
+    }
+
+    #[test]
+    fn tampered_data() {
+        let mut archive = sealed();
+        let mut data = b64.decode(&archive.data).unwrap();
+        data[0] ^= 1;
+        archive.data = b64.encode(data);
+
+        assert_eq!(
+            error(&archive, "passphrase"),
+            "Wrong passphrase or corrupted backup archive"
+        );
+    }
+
+    #[test]
+    fn tampered_nonce_salt_and_kdf() {
+        let mut archive = sealed();
+        let mut nonce = b64.decode(&archive.nonce).unwrap();
+        nonce[0] ^= 1;
+        archive.nonce = b64.encode(nonce);
+        assert!(archive.open::<Contents>("passphrase").is_err());
+
+        let mut archive = sealed();
+        archive.salt = generate_salt();
+        assert!(archive.open::<Contents>("passphrase").is_err());
+
+        let mut archive = sealed();
+        archive.kdf.t_cost = 2;
+        assert!(archive.open::<Contents>("passphrase").is_err());
+    }
+
+    #[test]
+    fn truncated_data() {
+        let mut archive = sealed();
+        let data = b64.decode(&archive.data).unwrap();
+        archive.data = b64.encode(&data[..data.len() - 1]);
+
+        assert!(archive.open::<Contents>("passphrase").is_err());
+    }
+
+    #[test]
+    fn foreign_or_newer_archives() {
+        let mut archive = sealed();
+        archive.format = "something-else".to_string();
+        assert_eq!(error(&archive, "passphrase"), "Not a qass backup archive");
+
+        let mut archive = sealed();
+        archive.version = ARCHIVE_VERSION + 1;
+        assert!(error(&archive, "passphrase").starts_with("Backup archive version 2 is newer"));
+    }
+}
@@ -0,0 +1,105 @@
+use std::{path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, bail};
+use device_query::Keycode;
+use directories::UserDirs;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::io::{config_dir, load_from_yaml};
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(rename = "type")]
+    pub typing: TypeConfig,
+    pub profiles: IndexMap<String, String>,
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Config> {
+        load_from_yaml(&config_dir()?.join("config.yml"))
+    }
+
+    // --store and --profile are exclusive and both win over $QASS_STORE, which wins over ~/.qass
+    pub fn store_dir(
+        &self,
+        store: Option<PathBuf>,
+        profile: Option<String>,
+    ) -> anyhow::Result<PathBuf> {
+        match (store, profile) {
+            (Some(_), Some(_)) => bail!("Use either --store or --profile, not both"),
+            (Some(store), None) => Ok(store),
+            (None, Some(profile)) => {
+                let dir = self
+                    .profiles
+                    .get(&profile)
+                    .ok_or_else(|| anyhow!("Profile '{}' not found in config", profile))?;
+                expand_home(dir)
+            }
+            (None, None) => match std::env::var_os("QASS_STORE") {
+                Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+                _ => config_dir(),
+            },
+        }
+    }
+}
+
+fn expand_home(path: &str) -> anyhow::Result<PathBuf> {
+    match path.strip_prefix("~/") {
+        Some(rest) => UserDirs::new()
+            .map(|ud| ud.home_dir().join(rest))
+            .ok_or(anyhow!("Could not determine home directory")),
+        None => Ok(PathBuf::from(path)),
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TypeConfig {
+    pub trigger_key: String,
+    pub timeout_secs: u64,
+    pub key_delay_ms: u64,
+    pub mode: TypeMode,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TypeMode {
+    Text,
+    Characters,
+}
+
+impl Default for TypeConfig {
+    fn default() -> Self {
+        TypeConfig {
+            trigger_key: "Control".to_string(),
+            timeout_secs: 5,
+            key_delay_ms: 0,
+            mode: TypeMode::Text,
+        }
+    }
+}
+
+impl TypeConfig {
+    // "Control", "Shift", "Alt" and "Meta" match the keys on either side
+    pub fn trigger_keys(&self) -> anyhow::Result<Vec<Keycode>> {
+        Ok(match self.trigger_key.as_str() {
+            "Control" => vec![Keycode::LControl, Keycode::RControl],
+            "Shift" => vec![Keycode::LShift, Keycode::RShift],
+            "Alt" => vec![Keycode::LAlt, Keycode::RAlt],
+            "Meta" => vec![Keycode::LMeta, Keycode::RMeta],
+            key => vec![Keycode::from_str(key)
+                .map_err(|_| anyhow!("Unknown trigger key '{}' in config", key))?],
+        })
+    }
+
+    pub fn prompt(&self, target: &str) -> String {
+        format!(
+            "Focus the target field and press {} to type {} ({}s timeout)...",
+            self.trigger_key.to_uppercase(),
+            target,
+            self.timeout_secs
+        )
+    }
+}
@@ -3,9 +3,107 @@ use aes_gcm_siv::{
     Aes256GcmSiv,
 };
 use anyhow::anyhow;
-use argon2::Argon2;
+use argon2::{Algorithm, Argon2, Params, Version};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD as b64, Engine as _};
+use clap::ValueEnum;
+use hkdf::Hkdf;
 use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Argon2Variant {
+    Argon2d,
+    Argon2i,
+    Argon2id,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub struct KdfParams {
+    pub algorithm: Argon2Variant,
+    pub version: u32,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+// what `Argon2::default()` used before the parameters were recorded, so it must never change
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            algorithm: Argon2Variant::Argon2id,
+            version: 0x13,
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.argon2().map(|_| ())
+    }
+
+    fn argon2(&self) -> anyhow::Result<Argon2<'static>> {
+        let algorithm = match self.algorithm {
+            Argon2Variant::Argon2d => Algorithm::Argon2d,
+            Argon2Variant::Argon2i => Algorithm::Argon2i,
+            Argon2Variant::Argon2id => Algorithm::Argon2id,
+        };
+        let version = Version::try_from(self.version)
+            .map_err(|_| anyhow!("Unsupported Argon2 version {:#x}", self.version))?;
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| anyhow!("Invalid Argon2 parameters: {}", e))?;
+
+        Ok(Argon2::new(algorithm, version, params))
+    }
+}
+
+// `per-entry` runs Argon2 for every encrypted secret, so no two secrets share any key material.
+// `batch` runs it once per operation and derives the key of each secret from that with HKDF.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum KdfMode {
+    #[default]
+    PerEntry,
+    Batch,
+}
+
+// Doubles the memory cost up to 1 GiB, then the iterations, until one derivation takes at least
+// `target` on this machine. Returns the parameters and the measured duration.
+pub fn calibrate(target: Duration) -> anyhow::Result<(KdfParams, Duration)> {
+    const MAX_M_COST: u32 = 1024 * 1024;
+
+    let mut params = KdfParams::default();
+    loop {
+        let elapsed = benchmark(&params)?;
+        if elapsed >= target {
+            return Ok((params, elapsed));
+        }
+
+        if params.m_cost < MAX_M_COST {
+            params.m_cost = (params.m_cost * 2).min(MAX_M_COST);
+        } else {
+            params.t_cost += 1;
+        }
+    }
+}
+
+pub fn benchmark(params: &KdfParams) -> anyhow::Result<Duration> {
+    let start = Instant::now();
+    derive_key("calibration", &generate_salt(), params)?;
+    Ok(start.elapsed())
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub enum Cipher {
+    #[default]
+    #[serde(rename = "aes-256-gcm-siv")]
+    Aes256GcmSiv,
+}
 
 pub fn generate_salt() -> String {
     let mut salt = [0u8; 16];
@@ -13,16 +111,26 @@ pub fn generate_salt() -> String {
     b64.encode(salt)
 }
 
-pub fn derive_key(master_pwd: &str, base64_salt: &str) -> anyhow::Result<[u8; 32]> {
+pub fn derive_key(
+    master_pwd: &str,
+    base64_salt: &str,
+    params: &KdfParams,
+) -> anyhow::Result<[u8; 32]> {
     let salt_bytes = b64.decode(base64_salt)?;
     let mut key = [0u8; 32];
-    Argon2::default()
+    params
+        .argon2()?
         .hash_password_into(master_pwd.as_bytes(), &salt_bytes, &mut key)
         .map_err(|_| anyhow!("Failed to derive key from master password"))?;
     Ok(key)
 }
 
-pub fn encrypt(cleartext: &str, key: &[u8; 32]) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+pub fn encrypt(
+    cleartext: &str,
+    key: &[u8; 32],
+    cipher: Cipher,
+) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    let Cipher::Aes256GcmSiv = cipher;
     let cipher = Aes256GcmSiv::new_from_slice(key)
         .map_err(|_| anyhow!("Failed to initialize cipher from derived key"))?;
 
@@ -43,8 +151,26 @@ pub fn encrypt(cleartext: &str, key: &[u8; 32]) -> anyhow::Result<(Vec<u8>, Vec<
     Ok((nonce.to_vec(), ciphertext))
 }
 
+// HKDF-SHA256 (RFC 5869), keyed by the batch key and salted per entry
+pub fn derive_subkey(key: &[u8; 32], base64_salt: &str) -> anyhow::Result<[u8; 32]> {
+    let salt_bytes = b64.decode(base64_salt)?;
+
+    let mut subkey = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt_bytes), key)
+        .expand(b"qass entry key", &mut subkey)
+        .map_err(|_| anyhow!("Failed to derive subkey"))?;
+
+    Ok(subkey)
+}
+
 // TODO: zeroizing?
-pub fn decrypt(ciphertext: &[u8], key: &[u8; 32], nonce: &[u8]) -> anyhow::Result<String> {
+pub fn decrypt(
+    ciphertext: &[u8],
+    key: &[u8; 32],
+    nonce: &[u8],
+    cipher: Cipher,
+) -> anyhow::Result<String> {
+    let Cipher::Aes256GcmSiv = cipher;
     let cipher = Aes256GcmSiv::new_from_slice(key)
         .map_err(|_| anyhow!("Failed to initialize cipher from derived key"))?;
     let nonce = aes_gcm_siv::Nonce::from_slice(nonce);
@@ -60,3 +186,81 @@ pub fn decrypt(ciphertext: &[u8], key: &[u8; 32], nonce: &[u8]) -> anyhow::Resul
 
     String::from_utf8(trimmed.to_vec()).map_err(|_| anyhow!("Result is not valid UTF-8"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // RFC 5869, Appendix A.1 to A.3
+    #[test]
+    fn hkdf_test_vectors() {
+        let vectors = [
+            (
+                "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+                "000102030405060708090a0b0c",
+                "f0f1f2f3f4f5f6f7f8f9",
+                "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf\
+                 34007208d5b887185865",
+            ),
+            (
+                "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f\
+                 202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f\
+                 404142434445464748494a4b4c4d4e4f",
+                "606162636465666768696a6b6c6d6e6f707172737475767778797a7b7c7d7e7f\
+                 808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f\
+                 a0a1a2a3a4a5a6a7a8a9aaabacadaeaf",
+                "b0b1b2b3b4b5b6b7b8b9babbbcbdbebfc0c1c2c3c4c5c6c7c8c9cacbcccdcecf\
+                 d0d1d2d3d4d5d6d7d8d9dadbdcdddedfe0e1e2e3e4e5e6e7e8e9eaebecedeeef\
+                 f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff",
+                "b11e398dc80327a1c8e7f78c596a49344f012eda2d4efad8a050cc4c19afa97c\
+                 59045a99cac7827271cb41c65e590e09da3275600c2f09b8367793a9aca3db71\
+                 cc30c58179ec3e87c14c01d5c1f3434f1d87",
+            ),
+            (
+                "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+                "",
+                "",
+                "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d\
+                 9d201395faa4b61a96c8",
+            ),
+        ];
+
+        for (ikm, salt, info, okm) in vectors {
+            let okm = hex(okm);
+            let mut output = vec![0u8; okm.len()];
+            Hkdf::<Sha256>::new(Some(&hex(salt)), &hex(ikm))
+                .expand(&hex(info), &mut output)
+                .unwrap();
+            assert_eq!(output, okm);
+        }
+    }
+
+    // entries encrypted in batch mode rely on subkeys never changing
+    #[test]
+    fn subkey_is_stable() {
+        let key: [u8; 32] = core::array::from_fn(|i| i as u8);
+        let subkey = derive_subkey(&key, "cWFzcyB0ZXN0IHNhbHQgMA").unwrap();
+
+        assert_eq!(
+            b64.encode(subkey),
+            "MP3O0FUfkqCHjjlVxi6g1cujPOQyv6TU8fAQrlXnzn4"
+        );
+    }
+
+    #[test]
+    fn subkeys_differ_per_salt() {
+        let key = [7u8; 32];
+
+        assert_ne!(
+            derive_subkey(&key, &generate_salt()).unwrap(),
+            derive_subkey(&key, &generate_salt()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,447 @@
+use base64::{engine::general_purpose::STANDARD as b64, Engine as _};
+use clap::ValueEnum;
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_json::json;
+use std::io::Write;
+
+use crate::login::UnencryptedLogin;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// 'path', 'username', 'password', 'url', 'notes' and a column per other field
+    Csv,
+    Json,
+    /// Bitwarden's unencrypted JSON export
+    BitwardenJson,
+    /// KeePass 2 XML, also readable by KeePassXC
+    KeepassXml,
+}
+
+pub fn write(
+    format: ExportFormat,
+    logins: &[UnencryptedLogin],
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    match format {
+        ExportFormat::Csv => write_csv(logins, out),
+        ExportFormat::Json => write_json(logins, out),
+        ExportFormat::BitwardenJson => write_bitwarden_json(logins, out),
+        ExportFormat::KeepassXml => write_keepass_xml(logins, out),
+    }
+}
+
+fn field<'a>(login: &'a UnencryptedLogin, name: &str) -> Option<&'a str> {
+    login
+        .extra_fields
+        .get(name)
+        .map(String::as_str)
+        .or_else(|| login.secret_fields.get(name).map(|v| v.as_str()))
+}
+
+// "work/github.com/alice" -> ("work/github.com", "alice")
+fn split_path(login_name: &str) -> (&str, &str) {
+    login_name.rsplit_once('/').unwrap_or(("", login_name))
+}
+
+fn write_csv(logins: &[UnencryptedLogin], out: &mut dyn Write) -> anyhow::Result<()> {
+    let mut columns = vec!["path", "username", "password", "url", "notes"];
+    for login in logins {
+        for name in login.extra_fields.keys().chain(login.secret_fields.keys()) {
+            if !columns.contains(&name.as_str()) {
+                columns.push(name);
+            }
+        }
+    }
+
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(&columns)?;
+    for login in logins {
+        writer.write_record(columns.iter().map(|column| match *column {
+            "path" => login.login_name.as_str(),
+            "password" => login.password.as_str(),
+            name => field(login, name).unwrap_or_default(),
+        }))?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonLogin<'a> {
+    path: &'a str,
+    password: &'a str,
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    fields: IndexMap<&'a str, &'a str>,
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    secret_fields: IndexMap<&'a str, &'a str>,
+}
+
+fn write_json(logins: &[UnencryptedLogin], out: &mut dyn Write) -> anyhow::Result<()> {
+    let logins: Vec<JsonLogin> = logins
+        .iter()
+        .map(|login| JsonLogin {
+            path: &login.login_name,
+            password: &login.password,
+            fields: login
+                .extra_fields
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect(),
+            secret_fields: login
+                .secret_fields
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect(),
+        })
+        .collect();
+
+    serde_json::to_writer_pretty(&mut *out, &logins)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+fn uuid() -> String {
+    let bytes: [u8; 16] = rand::random();
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-4{}-a{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[13..16],
+        &hex[17..20],
+        &hex[20..]
+    )
+}
+
+// The parent path becomes the folder and the last segment the item name. username, url, notes
+// and otp map to Bitwarden's own fields, other fields to custom (hidden if secret) fields.
+fn write_bitwarden_json(logins: &[UnencryptedLogin], out: &mut dyn Write) -> anyhow::Result<()> {
+    let mut folders = IndexMap::new();
+    let mut items = vec![];
+
+    for login in logins {
+        let (folder, name) = split_path(&login.login_name);
+        let folder_id =
+            (!folder.is_empty()).then(|| folders.entry(folder).or_insert_with(uuid).clone());
+
+        let fields: Vec<_> = login
+            .extra_fields
+            .iter()
+            .map(|(k, v)| (k, v.as_str(), 0))
+            .chain(login.secret_fields.iter().map(|(k, v)| (k, v.as_str(), 1)))
+            .filter(|(k, _, _)| !["username", "url", "notes", "otp"].contains(&k.as_str()))
+            .map(|(k, v, t)| json!({ "name": k, "value": v, "type": t }))
+            .collect();
+
+        items.push(json!({
+            "id": uuid(),
+            "folderId": folder_id,
+            "type": 1,
+            "name": name,
+            "notes": field(login, "notes"),
+            "favorite": false,
+            "fields": fields,
+            "login": {
+                "uris": field(login, "url")
+                    .map(|url| vec![json!({ "match": null, "uri": url })])
+                    .unwrap_or_default(),
+                "username": field(login, "username"),
+                "password": login.password.as_str(),
+                "totp": field(login, "otp"),
+            },
+        }));
+    }
+
+    let folders: Vec<_> = folders
+        .into_iter()
+        .map(|(name, id)| json!({ "id": id, "name": name }))
+        .collect();
+
+    serde_json::to_writer_pretty(
+        &mut *out,
+        &json!({ "encrypted": false, "folders": folders, "items": items }),
+    )?;
+    writeln!(out)?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Group<'a> {
+    groups: IndexMap<&'a str, Group<'a>>,
+    entries: Vec<&'a UnencryptedLogin>,
+}
+
+// Path segments become groups below a "qass" root group, mirroring the KeePass XML import.
+fn write_keepass_xml(logins: &[UnencryptedLogin], out: &mut dyn Write) -> anyhow::Result<()> {
+    let mut root = Group::default();
+    for login in logins {
+        let (path, _) = split_path(&login.login_name);
+        let group = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .fold(&mut root, |group, segment| {
+                group.groups.entry(segment).or_default()
+            });
+        group.entries.push(login);
+    }
+
+    writeln!(
+        out,
+        r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>"#
+    )?;
+    writeln!(out, "<KeePassFile>")?;
+    writeln!(out, "\t<Meta>")?;
+    writeln!(out, "\t\t<Generator>qass</Generator>")?;
+    writeln!(out, "\t</Meta>")?;
+    writeln!(out, "\t<Root>")?;
+    write_keepass_group("qass", &root, 2, out)?;
+    writeln!(out, "\t</Root>")?;
+    writeln!(out, "</KeePassFile>")?;
+
+    Ok(())
+}
+
+fn write_keepass_group(
+    name: &str,
+    group: &Group,
+    depth: usize,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let indent = "\t".repeat(depth);
+
+    writeln!(out, "{}<Group>", indent)?;
+    writeln!(out, "{}\t<UUID>{}</UUID>", indent, keepass_uuid())?;
+    writeln!(out, "{}\t<Name>{}</Name>", indent, escape(name))?;
+
+    for login in &group.entries {
+        writeln!(out, "{}\t<Entry>", indent)?;
+        writeln!(out, "{}\t\t<UUID>{}</UUID>", indent, keepass_uuid())?;
+
+        let (_, title) = split_path(&login.login_name);
+        let strings = [("Title", title, false), ("Password", &login.password, true)]
+            .into_iter()
+            .chain(
+                login
+                    .extra_fields
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str(), false)),
+            )
+            .chain(
+                login
+                    .secret_fields
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str(), true)),
+            );
+        for (key, value, protected) in strings {
+            let key = match key {
+                "username" => "UserName",
+                "url" => "URL",
+                "notes" => "Notes",
+                key => key,
+            };
+            let attribute = if protected {
+                r#" ProtectInMemory="True""#
+            } else {
+                ""
+            };
+
+            writeln!(
+                out,
+                "{}\t\t<String><Key>{}</Key><Value{}>{}</Value></String>",
+                indent,
+                escape(key),
+                attribute,
+                escape(value)
+            )?;
+        }
+
+        writeln!(out, "{}\t</Entry>", indent)?;
+    }
+
+    for (name, child) in &group.groups {
+        write_keepass_group(name, child, depth + 1, out)?;
+    }
+
+    writeln!(out, "{}</Group>", indent)?;
+    Ok(())
+}
+
+fn keepass_uuid() -> String {
+    b64.encode(rand::random::<[u8; 16]>())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::{read_csv, read_keepass_xml, CsvFormat};
+    use zeroize::Zeroizing;
+
+    fn logins() -> Vec<UnencryptedLogin> {
+        let mut github = UnencryptedLogin::new(
+            "work/github.com".to_string(),
+            Zeroizing::new("p,w\"1".to_string()),
+        );
+        github
+            .extra_fields
+            .insert("username".to_string(), "alice".to_string());
+        github
+            .extra_fields
+            .insert("notes".to_string(), "line 1\nline 2 <&>".to_string());
+        github
+            .secret_fields
+            .insert("pin".to_string(), Zeroizing::new("1234".to_string()));
+        github.secret_fields.insert(
+            "otp".to_string(),
+            Zeroizing::new("otpauth://totp/x".to_string()),
+        );
+
+        let mut mail = UnencryptedLogin::new("mail".to_string(), Zeroizing::new("pw".to_string()));
+        mail.extra_fields
+            .insert("recovery".to_string(), "r".to_string());
+
+        vec![github, mail]
+    }
+
+    fn export(format: ExportFormat) -> String {
+        let mut out = vec![];
+        write(format, &logins(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn fields(login: &UnencryptedLogin) -> Vec<(&str, &str)> {
+        login
+            .extra_fields
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+
+    fn secrets(login: &UnencryptedLogin) -> Vec<(&str, &str)> {
+        login
+            .secret_fields
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(
+            export(ExportFormat::Csv),
+            "path,username,password,url,notes,pin,otp,recovery\n\
+             work/github.com,alice,\"p,w\"\"1\",,\"line 1\nline 2 <&>\",1234,otpauth://totp/x,\n\
+             mail,,pw,,,,,r\n"
+        );
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.csv");
+        std::fs::write(&path, export(ExportFormat::Csv)).unwrap();
+
+        // the command from the README, plus the other columns of this export
+        let mut profile = CsvFormat::Custom.profile();
+        profile.password = Some("password".to_string());
+        profile.template = "{path}".to_string();
+        profile.fields = ["username", "url", "notes", "pin", "otp", "recovery"]
+            .iter()
+            .map(|f| (f.to_string(), f.to_string()))
+            .collect();
+        let (imported, skipped) = read_csv(path.to_str().unwrap(), &profile).unwrap();
+
+        assert!(skipped.is_empty());
+        assert_eq!(imported[0].login_name, "work/github.com");
+        assert_eq!(imported[0].password.as_str(), "p,w\"1");
+        assert_eq!(
+            fields(&imported[0]),
+            [
+                ("username", "alice"),
+                ("notes", "line 1\nline 2 <&>"),
+                ("pin", "1234")
+            ]
+        );
+        assert_eq!(secrets(&imported[0]), [("otp", "otpauth://totp/x")]);
+        assert_eq!(imported[1].login_name, "mail");
+        assert_eq!(fields(&imported[1]), [("recovery", "r")]);
+    }
+
+    #[test]
+    fn json_keeps_secret_fields_apart() {
+        let json: serde_json::Value = serde_json::from_str(&export(ExportFormat::Json)).unwrap();
+
+        assert_eq!(
+            json,
+            json!([
+                {
+                    "path": "work/github.com",
+                    "password": "p,w\"1",
+                    "fields": { "username": "alice", "notes": "line 1\nline 2 <&>" },
+                    "secret_fields": { "pin": "1234", "otp": "otpauth://totp/x" },
+                },
+                { "path": "mail", "password": "pw", "fields": { "recovery": "r" } },
+            ])
+        );
+    }
+
+    #[test]
+    fn bitwarden_json() {
+        let json: serde_json::Value =
+            serde_json::from_str(&export(ExportFormat::BitwardenJson)).unwrap();
+
+        assert_eq!(json["encrypted"], false);
+        assert_eq!(json["folders"][0]["name"], "work");
+        let (github, mail) = (&json["items"][0], &json["items"][1]);
+        assert_eq!(github["folderId"], json["folders"][0]["id"]);
+        assert_eq!(github["name"], "github.com");
+        assert_eq!(github["notes"], "line 1\nline 2 <&>");
+        assert_eq!(
+            github["login"],
+            json!({ "uris": [], "username": "alice", "password": "p,w\"1", "totp": "otpauth://totp/x" })
+        );
+        // type 1 is a hidden field
+        assert_eq!(
+            github["fields"],
+            json!([{ "name": "pin", "value": "1234", "type": 1 }])
+        );
+        assert_eq!(mail["folderId"], serde_json::Value::Null);
+        assert_eq!(
+            mail["fields"],
+            json!([{ "name": "recovery", "value": "r", "type": 0 }])
+        );
+    }
+
+    #[test]
+    fn keepass_xml_round_trip() {
+        let xml = export(ExportFormat::KeepassXml);
+        assert!(xml.contains("<Value>line 1\nline 2 &lt;&amp;&gt;</Value>"));
+        assert!(xml.contains(r#"<Key>pin</Key><Value ProtectInMemory="True">1234</Value>"#));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.xml");
+        std::fs::write(&path, xml).unwrap();
+        let (imported, skipped) = read_keepass_xml(path.to_str().unwrap()).unwrap();
+
+        assert!(skipped.is_empty());
+        assert_eq!(imported[0].login_name, "mail");
+        assert_eq!(imported[1].login_name, "work/github.com");
+        assert_eq!(imported[1].password.as_str(), "p,w\"1");
+        assert_eq!(
+            fields(&imported[1]),
+            [("username", "alice"), ("notes", "line 1\nline 2 <&>")]
+        );
+        assert_eq!(
+            secrets(&imported[1]),
+            [("pin", "1234"), ("otp", "otpauth://totp/x")]
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::bail;
+use clap::ValueEnum;
+use rand::Rng;
+use zeroize::Zeroizing;
+
+const WORDLIST: &str = include_str!("wordlist.txt");
+const AMBIGUOUS: &str = "Il1|O0o`'\"";
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CharClass {
+    Lower,
+    Upper,
+    Digits,
+    Symbols,
+}
+
+impl CharClass {
+    pub const ALL: [CharClass; 4] = [
+        CharClass::Lower,
+        CharClass::Upper,
+        CharClass::Digits,
+        CharClass::Symbols,
+    ];
+
+    fn chars(self) -> &'static str {
+        match self {
+            CharClass::Lower => "abcdefghijklmnopqrstuvwxyz",
+            CharClass::Upper => "ABCDEFGHIJKLMNOPQRSTUVWXYZ",
+            CharClass::Digits => "0123456789",
+            CharClass::Symbols => "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~",
+        }
+    }
+}
+
+pub enum Generator {
+    Characters {
+        length: usize,
+        charset: Vec<CharClass>,
+        exclude_ambiguous: bool,
+    },
+    Passphrase {
+        words: usize,
+    },
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator::Characters {
+            length: 24,
+            charset: CharClass::ALL.to_vec(),
+            exclude_ambiguous: false,
+        }
+    }
+}
+
+impl Generator {
+    pub fn generate(&self) -> anyhow::Result<Zeroizing<String>> {
+        match self {
+            Generator::Characters {
+                length,
+                charset,
+                exclude_ambiguous,
+            } => generate_characters(*length, charset, *exclude_ambiguous),
+            Generator::Passphrase { words } => generate_passphrase(*words),
+        }
+    }
+}
+
+fn generate_characters(
+    length: usize,
+    charset: &[CharClass],
+    exclude_ambiguous: bool,
+) -> anyhow::Result<Zeroizing<String>> {
+    if charset.is_empty() {
+        bail!("At least one character class is required");
+    }
+    if length < charset.len() {
+        bail!(
+            "Length must be at least {} to include every character class",
+            charset.len()
+        );
+    }
+
+    let classes: Vec<Vec<char>> = charset
+        .iter()
+        .map(|class| {
+            class
+                .chars()
+                .chars()
+                .filter(|c| !exclude_ambiguous || !AMBIGUOUS.contains(*c))
+                .collect()
+        })
+        .collect();
+    let pool: Vec<char> = classes.iter().flatten().copied().collect();
+
+    let mut rng = rand::rng();
+    loop {
+        // reserve upfront so the buffer is never reallocated, leaving stray copies behind
+        let mut password = Zeroizing::new(String::with_capacity(length * 4));
+        for _ in 0..length {
+            password.push(pool[rng.random_range(0..pool.len())]);
+        }
+
+        if classes
+            .iter()
+            .all(|class| password.chars().any(|c| class.contains(&c)))
+        {
+            return Ok(password);
+        }
+    }
+}
+
+fn generate_passphrase(words: usize) -> anyhow::Result<Zeroizing<String>> {
+    if words == 0 {
+        bail!("At least one word is required");
+    }
+
+    let wordlist: Vec<&str> = WORDLIST.lines().collect();
+
+    let mut rng = rand::rng();
+    let mut passphrase = Zeroizing::new(String::with_capacity(words * 9));
+    for i in 0..words {
+        if i > 0 {
+            passphrase.push('-');
+        }
+        passphrase.push_str(wordlist[rng.random_range(0..wordlist.len())]);
+    }
+
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn characters(length: usize, charset: &[CharClass], exclude_ambiguous: bool) -> Generator {
+        Generator::Characters {
+            length,
+            charset: charset.to_vec(),
+            exclude_ambiguous,
+        }
+    }
+
+    #[test]
+    fn every_class_appears() {
+        for _ in 0..200 {
+            let password = characters(4, &CharClass::ALL, false).generate().unwrap();
+
+            assert_eq!(password.chars().count(), 4);
+            for class in CharClass::ALL {
+                assert!(password.chars().any(|c| class.chars().contains(c)));
+            }
+        }
+    }
+
+    #[test]
+    fn only_requested_classes() {
+        let password = characters(64, &[CharClass::Digits, CharClass::Upper], false)
+            .generate()
+            .unwrap();
+
+        assert!(password
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase()));
+    }
+
+    #[test]
+    fn default_is_24_characters_of_all_classes() {
+        let password = Generator::default().generate().unwrap();
+
+        assert_eq!(password.len(), 24);
+        assert!(password.chars().any(|c| c.is_ascii_punctuation()));
+    }
+
+    #[test]
+    fn ambiguous_characters_are_excluded() {
+        for _ in 0..20 {
+            let password = characters(200, &CharClass::ALL, true).generate().unwrap();
+
+            assert!(!password.chars().any(|c| AMBIGUOUS.contains(c)));
+        }
+    }
+
+    #[test]
+    fn length_below_class_count_fails() {
+        let error = characters(3, &CharClass::ALL, false)
+            .generate()
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Length must be at least 4 to include every character class"
+        );
+        assert!(characters(1, &[], false).generate().is_err());
+    }
+
+    #[test]
+    fn passphrase_words() {
+        let wordlist: Vec<&str> = WORDLIST.lines().collect();
+        let passphrase = Generator::Passphrase { words: 6 }.generate().unwrap();
+
+        let words: Vec<&str> = passphrase.split('-').collect();
+        assert_eq!(words.len(), 6);
+        assert!(words.iter().all(|w| wordlist.contains(w)));
+    }
+
+    #[test]
+    fn zero_words_fail() {
+        let error = Generator::Passphrase { words: 0 }.generate().unwrap_err();
+
+        assert_eq!(error.to_string(), "At least one word is required");
+    }
+}
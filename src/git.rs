@@ -0,0 +1,242 @@
+use anyhow::{anyhow, bail};
+use std::{fs, path::Path, process::Command};
+
+// lock, staging and journal files of `State::save` and `io::save_to_file`
+const GITIGNORE: &str = "qass.lock\n*.new\n*.tmp\nsave.journal\n";
+
+pub fn is_enabled(dir: &Path) -> bool {
+    dir.join(".git").exists()
+}
+
+fn git(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow!("Could not run git: {}", e))?;
+
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+pub fn init(dir: &Path) -> anyhow::Result<()> {
+    if !is_enabled(dir) {
+        git(dir, &["init", "-q"])?;
+    }
+
+    let gitignore = dir.join(".gitignore");
+    if !gitignore.exists() {
+        fs::write(gitignore, GITIGNORE)?;
+    }
+
+    // commits need an identity, which may not be configured on this machine
+    if git(dir, &["config", "user.email"]).is_err() {
+        git(dir, &["config", "user.name", "qass"])?;
+        git(dir, &["config", "user.email", "qass@localhost"])?;
+    }
+
+    commit(dir, "Initialize store")
+}
+
+// commits all changes in the store, if there are any
+pub fn commit(dir: &Path, message: &str) -> anyhow::Result<()> {
+    git(dir, &["add", "--all"])?;
+    if git(dir, &["diff", "--cached", "--quiet"]).is_ok() {
+        return Ok(());
+    }
+
+    git(dir, &["commit", "--quiet", "--message", message])?;
+    Ok(())
+}
+
+pub fn log(dir: &Path, count: usize) -> anyhow::Result<String> {
+    git(
+        dir,
+        &[
+            "log",
+            &format!("--max-count={}", count),
+            "--date=format:%Y-%m-%d %H:%M",
+            "--format=%h  %ad  %s",
+        ],
+    )
+}
+
+// the full hash of `commit`, e.g. of "HEAD"
+pub fn resolve(dir: &Path, commit: &str) -> anyhow::Result<String> {
+    let spec = format!("{}^{{commit}}", commit);
+    Ok(git(dir, &["rev-parse", "--verify", &spec])?
+        .trim()
+        .to_string())
+}
+
+pub fn subject(dir: &Path, commit: &str) -> anyhow::Result<String> {
+    Ok(git(dir, &["log", "--max-count=1", "--format=%s", commit])?
+        .trim()
+        .to_string())
+}
+
+// reverts `commit` with a new commit, so the undo itself can be undone
+pub fn revert(dir: &Path, commit: &str) -> anyhow::Result<()> {
+    // the first commit has no parent, reverting it would remove the whole store
+    let parents = git(dir, &["rev-list", "--parents", "--max-count=1", commit])?;
+    if parents.split_whitespace().count() < 2 {
+        bail!(
+            "Cannot undo {}, it is the first commit of the store",
+            commit
+        );
+    }
+
+    if git(dir, &["revert", "--no-edit", commit]).is_err() {
+        let _ = git(dir, &["revert", "--abort"]);
+        bail!("Could not revert {}, later changes conflict with it", commit);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        init(dir.path()).unwrap();
+        dir
+    }
+
+    fn change(dir: &Path, contents: &str, message: &str) -> String {
+        fs::write(dir.join("logins.yml"), contents).unwrap();
+        commit(dir, message).unwrap();
+        head(dir)
+    }
+
+    fn head(dir: &Path) -> String {
+        git(dir, &["rev-parse", "HEAD"]).unwrap().trim().to_string()
+    }
+
+    fn subjects(dir: &Path, count: usize) -> Vec<String> {
+        log(dir, count)
+            .unwrap()
+            .lines()
+            .map(|line| line.splitn(3, "  ").nth(2).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn init_creates_repository() {
+        let dir = store();
+
+        assert!(is_enabled(dir.path()));
+        assert_eq!(
+            fs::read_to_string(dir.path().join(".gitignore")).unwrap(),
+            GITIGNORE
+        );
+        assert_eq!(subjects(dir.path(), 10), ["Initialize store"]);
+    }
+
+    #[test]
+    fn init_keeps_existing_repository() {
+        let dir = store();
+        change(dir.path(), "a", "Add a");
+
+        init(dir.path()).unwrap();
+
+        assert_eq!(subjects(dir.path(), 10), ["Add a", "Initialize store"]);
+    }
+
+    #[test]
+    fn commit_skips_unchanged_store() {
+        let dir = store();
+        let first = change(dir.path(), "a", "Add a");
+        let second = change(dir.path(), "a", "Add a again");
+
+        assert_eq!(first, second);
+        assert_eq!(subjects(dir.path(), 10), ["Add a", "Initialize store"]);
+    }
+
+    #[test]
+    fn commit_ignores_lock_and_staging_files() {
+        let dir = store();
+        fs::write(dir.path().join("qass.lock"), "").unwrap();
+        fs::write(dir.path().join("logins.yml.new"), "").unwrap();
+
+        commit(dir.path(), "Nothing").unwrap();
+
+        assert_eq!(subjects(dir.path(), 10), ["Initialize store"]);
+    }
+
+    #[test]
+    fn log_is_limited_to_count() {
+        let dir = store();
+        change(dir.path(), "a", "Add a");
+        change(dir.path(), "b", "Add b");
+
+        assert_eq!(subjects(dir.path(), 2), ["Add b", "Add a"]);
+        assert_eq!(subject(dir.path(), "HEAD~1").unwrap(), "Add a");
+    }
+
+    #[test]
+    fn resolve_pins_commits() {
+        let dir = store();
+        let a = change(dir.path(), "a", "Add a");
+
+        assert_eq!(resolve(dir.path(), "HEAD").unwrap(), a);
+        assert_eq!(resolve(dir.path(), &a[..7]).unwrap(), a);
+        change(dir.path(), "b", "Add b");
+        assert_eq!(resolve(dir.path(), "HEAD~1").unwrap(), a);
+        assert!(resolve(dir.path(), "nonexistent").is_err());
+    }
+
+    #[test]
+    fn revert_creates_commit() {
+        let dir = store();
+        change(dir.path(), "a", "Add a");
+        let b = change(dir.path(), "b", "Add b");
+
+        revert(dir.path(), &b).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("logins.yml")).unwrap(),
+            "a"
+        );
+        assert_eq!(subjects(dir.path(), 1), ["Revert \"Add b\""]);
+    }
+
+    #[test]
+    fn revert_conflict_is_aborted() {
+        let dir = store();
+        change(dir.path(), "a", "Add a");
+        let b = change(dir.path(), "b", "Add b");
+        change(dir.path(), "c", "Add c");
+
+        let error = revert(dir.path(), &b).unwrap_err().to_string();
+
+        assert!(error.contains("later changes conflict"), "{}", error);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("logins.yml")).unwrap(),
+            "c"
+        );
+        assert_eq!(git(dir.path(), &["status", "--porcelain"]).unwrap(), "");
+    }
+
+    #[test]
+    fn revert_refuses_first_commit() {
+        let dir = store();
+        let first = head(dir.path());
+        change(dir.path(), "a", "Add a");
+
+        let error = revert(dir.path(), &first).unwrap_err().to_string();
+
+        assert!(error.contains("first commit"), "{}", error);
+        assert!(dir.path().join(".gitignore").exists());
+        assert_eq!(subjects(dir.path(), 1), ["Add a"]);
+    }
+}
@@ -1,9 +1,15 @@
 use std::{
     collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
     time::{Duration, Instant},
 };
 
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 use eframe::egui::{
     self,
     text::{CCursor, CCursorRange},
@@ -11,9 +17,15 @@ use eframe::egui::{
 use enigo::{Enigo, Mouse, Settings};
 use zeroize::Zeroizing;
 
-use crate::gui_widget::PasswordEdit;
+use crate::{
+    autotype::{self, Action},
+    config::Config,
+    generator::Generator,
+    gui_widget::PasswordEdit,
+    totp::Totp,
+};
 
-pub fn run() -> anyhow::Result<()> {
+pub fn run(config: Config, store: PathBuf) -> anyhow::Result<()> {
     let cursor_pos = {
         let enigo = Enigo::new(&Settings::default())?;
         let (x, y) = enigo.location().unwrap_or((100, 100));
@@ -31,7 +43,11 @@ pub fn run() -> anyhow::Result<()> {
         ..Default::default()
     };
 
-    let state = QassGui::search(String::new());
+    let state = QassGui {
+        config,
+        store,
+        screen: Screen::search(String::new()),
+    };
 
     eframe::run_native("qass", options, Box::new(|_cc| Ok(Box::new(state))))
         .map_err(|e| anyhow!("Failed to run qass GUI: {:?}", e))?;
@@ -39,8 +55,14 @@ pub fn run() -> anyhow::Result<()> {
     Ok(())
 }
 
+struct QassGui {
+    config: Config,
+    store: PathBuf,
+    screen: Screen,
+}
+
 #[derive(Clone)]
-enum QassGui {
+enum Screen {
     Search {
         search_text: String,
         refocused: bool,
@@ -55,30 +77,58 @@ enum QassGui {
         search_text: String,
         error_msg: String,
     },
+    Info {
+        search_text: String,
+        msg: String,
+    },
     PasswordPrompt {
         login_name: String,
         password: Zeroizing<String>,
     },
-    PasswordTypingConfirmation {
+    NewLoginPrompt {
         login_name: String,
         password: Zeroizing<String>,
+    },
+    SyncPrompt {
+        path: String,
+        password: Zeroizing<String>,
+    },
+    Syncing {
+        path: String,
+        progress: Arc<SyncProgress>,
+    },
+    PasswordTypingConfirmation {
+        login_name: String,
+        actions: Vec<Action>,
+        otp: Option<Totp>,
+        first_frame: Instant,
+        delay: Duration,
+    },
+    OtpTypingConfirmation {
+        login_name: String,
+        otp: Totp,
         first_frame: Instant,
         delay: Duration,
     },
 }
 
-impl QassGui {
+#[derive(Default)]
+struct SyncProgress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    cancel: AtomicBool,
+    result: Mutex<Option<anyhow::Result<usize>>>,
+}
+
+impl Screen {
     fn search(search_text: String) -> Self {
         Self::Search {
-            search_text: search_text,
+            search_text,
             refocused: false,
         }
     }
 
-    fn filtered_suggestions<'a>(
-        search_text: String,
-        suggestions: &'a Vec<String>,
-    ) -> Vec<(usize, &'a str)> {
+    fn filtered_suggestions(search_text: String, suggestions: &[String]) -> Vec<(usize, &str)> {
         if search_text.is_empty() {
             return vec![];
         }
@@ -106,8 +156,8 @@ impl QassGui {
             .collect()
     }
 
-    fn suggestions_state(search_text: String) -> Self {
-        match crate::api::State::load() {
+    fn suggestions_state(store: &Path, search_text: String) -> Self {
+        match crate::api::State::load(store) {
             Ok(state) => {
                 let suggestions = state.list();
 
@@ -115,7 +165,7 @@ impl QassGui {
                 let filtered_suggestions =
                     Self::filtered_suggestions(search_text.clone(), &suggestions);
                 if filtered_suggestions.len() == 1 {
-                    return QassGui::search(filtered_suggestions[0].1.to_owned());
+                    return Screen::search(filtered_suggestions[0].1.to_owned());
                 }
 
                 Self::SearchSuggestions {
@@ -125,17 +175,96 @@ impl QassGui {
                     suggestions,
                 }
             }
-            Err(e) => QassGui::Error {
+            Err(e) => Screen::Error {
                 search_text,
                 error_msg: format!("Failed to load logins: {}", e),
             },
         }
     }
 
-    fn password_type(login_name: String, password: Zeroizing<String>) -> Self {
+    fn add_generated(
+        store: &Path,
+        login_name: &str,
+        master_password: Zeroizing<String>,
+    ) -> anyhow::Result<Zeroizing<String>> {
+        let mut state = crate::api::State::load_mut(store)?;
+
+        if login_name.is_empty() {
+            bail!("Login path must not be empty");
+        }
+        if state.list().iter().any(|l| l == login_name) {
+            bail!("Login '{}' already exists", login_name);
+        }
+
+        let password = Generator::default().generate()?;
+        state.add(login_name.to_string(), password.clone(), master_password)?;
+        state.save(&format!("Add {}", login_name))?;
+
+        Ok(password)
+    }
+
+    // syncs on a worker thread, so the progress bar keeps updating
+    fn start_sync(store: &Path, path: String, master_password: Zeroizing<String>) -> Self {
+        let progress = Arc::new(SyncProgress::default());
+
+        let store = store.to_path_buf();
+        let sync_path = if path.is_empty() {
+            "/".to_string()
+        } else {
+            path.clone()
+        };
+        let worker_progress = progress.clone();
+        thread::spawn(move || {
+            let p = &worker_progress;
+            let result = crate::api::State::load_mut(&store).and_then(|mut state| {
+                let count = state.sync(sync_path, master_password, &mut |done, total| {
+                    p.done.store(done, Ordering::Relaxed);
+                    p.total.store(total, Ordering::Relaxed);
+                    !p.cancel.load(Ordering::Relaxed)
+                })?;
+                state.save(&format!("Encrypt {} entries", count))?;
+                Ok(count)
+            });
+            *p.result.lock().unwrap() = Some(result);
+        });
+
+        Self::Syncing { path, progress }
+    }
+
+    fn decrypt_login(
+        store: &Path,
+        login_name: &str,
+        master_password: Zeroizing<String>,
+    ) -> anyhow::Result<(Vec<Action>, Option<Totp>)> {
+        let state = crate::api::State::load(store)?;
+
+        let actions = autotype::resolve(&state, login_name, None, master_password.clone())?;
+
+        let has_otp = state.secret_fields(login_name)?.iter().any(|f| f == "otp");
+        let otp = if has_otp && !Action::uses_otp(&actions) {
+            let secret = state.get_secret(login_name.to_string(), "otp", master_password)?;
+            Some(Totp::parse(&secret)?)
+        } else {
+            None
+        };
+
+        Ok((actions, otp))
+    }
+
+    fn password_type(login_name: String, actions: Vec<Action>, otp: Option<Totp>) -> Self {
         Self::PasswordTypingConfirmation {
             login_name,
-            password,
+            actions,
+            otp,
+            first_frame: Instant::now(),
+            delay: Duration::from_secs_f32(0.1),
+        }
+    }
+
+    fn otp_type(login_name: String, otp: Totp) -> Self {
+        Self::OtpTypingConfirmation {
+            login_name,
+            otp,
             first_frame: Instant::now(),
             delay: Duration::from_secs_f32(0.1),
         }
@@ -144,8 +273,10 @@ impl QassGui {
 
 impl eframe::App for QassGui {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        let panel_frame = match self {
-            QassGui::PasswordTypingConfirmation { .. } => egui::Frame::default(),
+        let panel_frame = match self.screen {
+            Screen::PasswordTypingConfirmation { .. } | Screen::OtpTypingConfirmation { .. } => {
+                egui::Frame::default()
+            }
             _ => {
                 let mut frame = egui::Frame::default();
                 let original_color = frame.fill;
@@ -162,13 +293,18 @@ impl eframe::App for QassGui {
         let panel = egui::CentralPanel::default().frame(panel_frame);
         panel.show(ctx, |ui| {
             if ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
-                std::process::exit(0);
+                match &self.screen {
+                    Screen::Syncing { progress, .. } => {
+                        progress.cancel.store(true, Ordering::Relaxed)
+                    }
+                    _ => std::process::exit(0),
+                }
             }
 
             let mut next_state = None;
 
-            match self {
-                QassGui::Search {
+            match &mut self.screen {
+                Screen::Search {
                     search_text,
                     refocused,
                 } => {
@@ -191,17 +327,32 @@ impl eframe::App for QassGui {
                     }
 
                     if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Tab)) {
-                        next_state = Some(QassGui::suggestions_state(search_text.clone()));
+                        next_state =
+                            Some(Screen::suggestions_state(&self.store, search_text.clone()));
                     }
 
-                    if ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
-                        next_state = Some(QassGui::PasswordPrompt {
-                            login_name: search_text.clone(),
+                    if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::S)) {
+                        next_state = Some(Screen::SyncPrompt {
+                            path: search_text.clone(),
                             password: String::new().into(),
                         });
                     }
+
+                    if ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        next_state = Some(if ctx.input(|i| i.modifiers.command) {
+                            Screen::NewLoginPrompt {
+                                login_name: search_text.clone(),
+                                password: String::new().into(),
+                            }
+                        } else {
+                            Screen::PasswordPrompt {
+                                login_name: search_text.clone(),
+                                password: String::new().into(),
+                            }
+                        });
+                    }
                 }
-                QassGui::SearchSuggestions {
+                Screen::SearchSuggestions {
                     search_text,
                     selected_suggestion,
                     prev_selected_suggestion,
@@ -216,7 +367,7 @@ impl eframe::App for QassGui {
                     ui.separator();
 
                     let filtered_suggestions =
-                        QassGui::filtered_suggestions(search_text.clone(), suggestions);
+                        Screen::filtered_suggestions(search_text.clone(), suggestions);
 
                     if !filtered_suggestions.is_empty() {
                         *selected_suggestion =
@@ -247,7 +398,7 @@ impl eframe::App for QassGui {
 
                                     if response.clicked() {
                                         *search_text = suggestion;
-                                        next_state = Some(QassGui::search(search_text.to_string()));
+                                        next_state = Some(Screen::search(search_text.to_string()));
                                     }
 
                                     if response.hovered() {
@@ -281,11 +432,11 @@ impl eframe::App for QassGui {
                                 .1
                                 .to_string();
 
-                            next_state = Some(QassGui::search(search_text.to_string()));
+                            next_state = Some(Screen::search(search_text.to_string()));
                         }
                     }
                 }
-                QassGui::PasswordPrompt {
+                Screen::PasswordPrompt {
                     login_name,
                     password,
                 } => {
@@ -295,48 +446,157 @@ impl eframe::App for QassGui {
                     ui.colored_label(ui.visuals().strong_text_color(), "Enter password...");
 
                     if ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
-                        let pwd = crate::api::State::load().and_then(|s| {
-                            s.get(login_name.to_string(), Zeroizing::new(password.to_string()))
-                        });
+                        let pwd = Screen::decrypt_login(
+                            &self.store,
+                            login_name,
+                            Zeroizing::new(password.to_string()),
+                        );
 
                         next_state = Some(match pwd {
-                            Ok(password) => {
-                                QassGui::password_type(login_name.to_string(), password)
+                            Ok((actions, otp)) => {
+                                Screen::password_type(login_name.to_string(), actions, otp)
                             }
-                            Err(e) => QassGui::Error {
+                            Err(e) => Screen::Error {
                                 search_text: login_name.to_string(),
                                 error_msg: format!("Failed to decrypt: {}", e),
                             },
                         });
                     }
                 }
-                QassGui::PasswordTypingConfirmation {
+                Screen::NewLoginPrompt {
                     login_name,
                     password,
+                } => {
+                    let pwd_response = ui.add(PasswordEdit::new(password));
+                    pwd_response.request_focus();
+
+                    ui.colored_label(
+                        ui.visuals().strong_text_color(),
+                        "Enter password to store a generated password...",
+                    );
+
+                    if ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        let generated = Screen::add_generated(
+                            &self.store,
+                            login_name,
+                            Zeroizing::new(password.to_string()),
+                        );
+
+                        next_state = Some(match generated {
+                            Ok(password) => Screen::password_type(
+                                login_name.to_string(),
+                                vec![Action::Text(password)],
+                                None,
+                            ),
+                            Err(e) => Screen::Error {
+                                search_text: login_name.to_string(),
+                                error_msg: format!("Failed to add login: {}", e),
+                            },
+                        });
+                    }
+                }
+                Screen::SyncPrompt { path, password } => {
+                    let pwd_response = ui.add(PasswordEdit::new(password));
+                    pwd_response.request_focus();
+
+                    ui.colored_label(
+                        ui.visuals().strong_text_color(),
+                        "Enter password to encrypt cleartext logins...",
+                    );
+
+                    if ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        next_state = Some(Screen::start_sync(
+                            &self.store,
+                            path.clone(),
+                            Zeroizing::new(password.to_string()),
+                        ));
+                    }
+                }
+                Screen::Syncing { path, progress } => {
+                    let done = progress.done.load(Ordering::Relaxed);
+                    let total = progress.total.load(Ordering::Relaxed);
+                    let fraction = if total == 0 {
+                        0.0
+                    } else {
+                        done as f32 / total as f32
+                    };
+
+                    ui.add(egui::ProgressBar::new(fraction).text(format!("{}/{}", done, total)));
+                    ui.colored_label(
+                        ui.visuals().strong_text_color(),
+                        "Syncing... Press Escape to cancel.",
+                    );
+                    ctx.request_repaint_after(Duration::from_millis(100));
+
+                    if let Some(result) = progress.result.lock().unwrap().take() {
+                        next_state = Some(match result {
+                            Ok(count) => Screen::Info {
+                                search_text: path.clone(),
+                                msg: format!("Successfully synced {} entries", count),
+                            },
+                            Err(e) => Screen::Error {
+                                search_text: path.clone(),
+                                error_msg: format!("Failed to sync: {}", e),
+                            },
+                        });
+                    }
+                }
+                Screen::PasswordTypingConfirmation {
+                    login_name,
+                    actions,
+                    otp,
                     first_frame,
                     delay,
                 } => {
                     ctx.send_viewport_cmd(egui::ViewportCommand::MousePassthrough(true));
                     ui.colored_label(
                         ui.visuals().strong_text_color(),
-                        "Focus the target field and press CONTROL to type password (5s timeout)...",
+                        self.config.typing.prompt("login"),
                     );
 
                     if first_frame.elapsed() > *delay {
-                        let typing = crate::type_password_text(&password);
+                        let typing = crate::type_sequence(actions, &self.config.typing);
 
                         if let Err(e) = typing {
                             ctx.send_viewport_cmd(egui::ViewportCommand::MousePassthrough(false));
-                            next_state = Some(QassGui::Error {
+                            next_state = Some(Screen::Error {
                                 search_text: login_name.to_string(),
                                 error_msg: format!("Failed to type password: {}", e),
                             });
+                        } else if let Some(otp) = otp.take() {
+                            next_state = Some(Screen::otp_type(login_name.to_string(), otp));
                         } else {
                             std::process::exit(0);
                         }
                     }
                 }
-                QassGui::Error {
+                Screen::OtpTypingConfirmation {
+                    login_name,
+                    otp,
+                    first_frame,
+                    delay,
+                } => {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::MousePassthrough(true));
+                    ui.colored_label(
+                        ui.visuals().strong_text_color(),
+                        self.config.typing.prompt("one-time code"),
+                    );
+
+                    if first_frame.elapsed() > *delay {
+                        let typing = crate::type_otp_code(otp, &self.config.typing);
+
+                        if let Err(e) = typing {
+                            ctx.send_viewport_cmd(egui::ViewportCommand::MousePassthrough(false));
+                            next_state = Some(Screen::Error {
+                                search_text: login_name.to_string(),
+                                error_msg: format!("Failed to type one-time code: {}", e),
+                            });
+                        } else {
+                            std::process::exit(0);
+                        }
+                    }
+                }
+                Screen::Error {
                     search_text,
                     error_msg,
                 } => {
@@ -350,13 +610,27 @@ impl eframe::App for QassGui {
                     ui.colored_label(ui.visuals().warn_fg_color, "Press Tab to continue.");
 
                     if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Tab)) {
-                        next_state = Some(QassGui::search(search_text.clone()));
+                        next_state = Some(Screen::search(search_text.clone()));
+                    }
+                }
+                Screen::Info { search_text, msg } => {
+                    let search_response = ui.add_sized(
+                        ui.available_size() * egui::vec2(1.0, 0.0),
+                        egui::TextEdit::singleline(search_text),
+                    );
+                    search_response.request_focus();
+
+                    ui.colored_label(ui.visuals().strong_text_color(), msg);
+                    ui.colored_label(ui.visuals().strong_text_color(), "Press Tab to continue.");
+
+                    if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Tab)) {
+                        next_state = Some(Screen::search(search_text.clone()));
                     }
                 }
             };
 
             if let Some(new_state) = next_state {
-                *self = new_state;
+                self.screen = new_state;
             }
         });
     }
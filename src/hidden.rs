@@ -1,11 +1,15 @@
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
 
-use crate::login::{LoginEntry, SaltEntry};
+use crate::{
+    api::FORMAT_VERSION,
+    login::{rename_legacy_fields, LoginEntry, SaltEntry},
+};
 
 pub type HiddenMapIndex = IndexMap<String, HiddenMap>;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct HiddenMap {
     pub logins: EncryptedHiddenMap,
     pub salt: SaltEntry,
@@ -15,16 +19,60 @@ type EncryptedHiddenMap = String;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UnsaltedHiddenMap {
+    // the store format version the map was written with, missing before format version 4
+    #[serde(default)]
+    pub version: u32,
     pub logins: IndexMap<String, HiddenEntry>,
 }
 
+impl Default for UnsaltedHiddenMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UnsaltedHiddenMap {
     pub fn new() -> Self {
         Self {
+            version: FORMAT_VERSION,
             logins: IndexMap::new(),
         }
     }
 
+    // A hidden map keeps the format it was hidden with even when the store is upgraded, so
+    // fields of older maps that would now be read as timestamps or history are renamed like
+    // those of logins.yml. The map is in the current format afterwards.
+    pub fn from_yaml(yaml: &str) -> anyhow::Result<Self> {
+        let mut raw: Value = serde_yaml::from_str(yaml)?;
+        let version = raw.get("version").and_then(Value::as_u64).unwrap_or(0);
+
+        if version < 4 {
+            let logins = raw.get_mut("logins").and_then(Value::as_mapping_mut);
+            for (path, entry) in logins.into_iter().flatten() {
+                let (Some(path), Some(login)) = (path.as_str(), entry.get_mut("login")) else {
+                    continue;
+                };
+                let renamed = rename_legacy_fields(login, path);
+
+                let salt_fields = entry
+                    .get_mut("salt")
+                    .and_then(|salt| salt.get_mut("fields"))
+                    .and_then(Value::as_mapping_mut);
+                if let Some(salt_fields) = salt_fields {
+                    for (_, key, name) in renamed {
+                        if let Some(field_salt) = salt_fields.shift_remove(key.as_str()) {
+                            salt_fields.insert(name.into(), field_salt);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut hidden: Self = serde_yaml::from_value(raw)?;
+        hidden.version = FORMAT_VERSION;
+        Ok(hidden)
+    }
+
     pub fn insert(
         &mut self,
         key: String,
@@ -0,0 +1,1041 @@
+use anyhow::{anyhow, bail};
+use clap::ValueEnum;
+use indexmap::IndexMap;
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+    process::{Command, Stdio},
+};
+use zeroize::Zeroizing;
+
+use crate::login::{UnencryptedLogin, RESERVED_FIELDS};
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportSource {
+    Csv,
+    /// A password-store directory, or "-" to read decrypted entries from stdin
+    Pass,
+    /// An unencrypted KeePass 2 XML export
+    KeepassXml,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CsvFormat {
+    /// 'url', 'username' and 'password' columns
+    Generic,
+    Chrome,
+    Firefox,
+    Bitwarden,
+    Keepassxc,
+    #[value(name = "1password")]
+    OnePassword,
+    /// Only the columns given with --*-column and --field
+    Custom,
+}
+
+// Maps CSV columns to logins. Column names are matched case-insensitively.
+pub struct CsvProfile {
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub title: Option<String>,
+    pub group: Option<String>,
+    // rows whose column doesn't have this value are skipped, e.g. notes and cards in Bitwarden
+    pub kind: Option<(String, String)>,
+    // column -> extra field, "otp" becomes a secret field
+    pub fields: Vec<(String, String)>,
+    // placeholders: {url}, {host}, {username}, {title}, {group} and any column name
+    pub template: String,
+}
+
+// an entry left out of an import, reported as e.g. "line 3: has no password"
+pub struct Skipped {
+    pub source: String,
+    pub reason: String,
+}
+
+fn columns(names: &[&str]) -> Vec<(String, String)> {
+    names
+        .iter()
+        .map(|n| {
+            let (column, field) = n.split_once('=').unwrap_or((n, n));
+            (column.to_string(), field.to_string())
+        })
+        .collect()
+}
+
+impl CsvFormat {
+    pub fn profile(self) -> CsvProfile {
+        let profile = |url: &str, username: &str, password: &str, template: &str| CsvProfile {
+            url: Some(url.to_string()),
+            username: Some(username.to_string()),
+            password: Some(password.to_string()),
+            title: None,
+            group: None,
+            kind: None,
+            fields: vec![],
+            template: template.to_string(),
+        };
+
+        match self {
+            CsvFormat::Generic => profile("url", "username", "password", "{url}/{username}"),
+            CsvFormat::Chrome => CsvProfile {
+                title: Some("name".to_string()),
+                fields: columns(&["note=notes"]),
+                ..profile("url", "username", "password", "{host}/{username}")
+            },
+            CsvFormat::Firefox => profile("url", "username", "password", "{host}/{username}"),
+            CsvFormat::Bitwarden => CsvProfile {
+                title: Some("name".to_string()),
+                group: Some("folder".to_string()),
+                kind: Some(("type".to_string(), "login".to_string())),
+                fields: columns(&["notes", "login_totp=otp"]),
+                ..profile(
+                    "login_uri",
+                    "login_username",
+                    "login_password",
+                    "{host}/{username}",
+                )
+            },
+            CsvFormat::Keepassxc => CsvProfile {
+                title: Some("Title".to_string()),
+                group: Some("Group".to_string()),
+                fields: columns(&["URL=url", "Notes=notes", "TOTP=otp"]),
+                ..profile("URL", "Username", "Password", "{group}/{title}")
+            },
+            CsvFormat::OnePassword => CsvProfile {
+                title: Some("Title".to_string()),
+                fields: columns(&["Notes=notes", "OTPAuth=otp"]),
+                ..profile("Url", "Username", "Password", "{host}/{username}")
+            },
+            CsvFormat::Custom => CsvProfile {
+                url: None,
+                username: None,
+                password: None,
+                title: None,
+                group: None,
+                kind: None,
+                fields: vec![],
+                template: "{host}/{username}".to_string(),
+            },
+        }
+    }
+}
+
+// "https://www.example.com:8080/login" -> "example.com"
+pub fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map(|(_, r)| r).unwrap_or(url);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+    host.strip_prefix("www.").unwrap_or(host)
+}
+
+pub fn expand_template(
+    template: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> anyhow::Result<String> {
+    let mut path = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        path.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("Unterminated '{{' in path template"))?;
+        let name = &rest[start + 1..start + end];
+        path.push_str(&lookup(name).ok_or_else(|| anyhow!("Unknown placeholder '{{{}}}'", name))?);
+        rest = &rest[start + end + 1..];
+    }
+    path.push_str(rest);
+
+    Ok(path.trim_matches('/').to_string())
+}
+
+pub fn read_csv(
+    path: &str,
+    profile: &CsvProfile,
+) -> anyhow::Result<(Vec<UnencryptedLogin>, Vec<Skipped>)> {
+    let file = std::fs::File::open(path)?;
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(file);
+
+    let headers = reader.headers()?.clone();
+    let index = |column: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(column))
+            .ok_or_else(|| anyhow!("Missing '{}' column", column))
+    };
+    let optional_index = |column: &Option<String>| column.as_deref().map(index).transpose();
+
+    let password_idx = index(
+        profile
+            .password
+            .as_deref()
+            .ok_or_else(|| anyhow!("No password column given"))?,
+    )?;
+    let url_idx = optional_index(&profile.url)?;
+    let username_idx = optional_index(&profile.username)?;
+    let title_idx = optional_index(&profile.title)?;
+    let group_idx = optional_index(&profile.group)?;
+    let kind = match &profile.kind {
+        Some((column, value)) => Some((index(column)?, value)),
+        None => None,
+    };
+    let fields = profile
+        .fields
+        .iter()
+        .filter_map(|(column, field)| index(column).ok().map(|i| (i, field)))
+        .collect::<Vec<_>>();
+
+    let mut logins = vec![];
+    let mut skipped = vec![];
+    for result in reader.records() {
+        let record = result?;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let mut skip = |reason: String| {
+            skipped.push(Skipped {
+                source: format!("line {}", line),
+                reason,
+            })
+        };
+
+        if record.len() < headers.len() {
+            skip(format!("has {} of {} columns", record.len(), headers.len()));
+            continue;
+        }
+
+        let get = |idx: Option<usize>| idx.map(|i| record[i].trim()).unwrap_or_default();
+
+        if let Some((idx, value)) = kind {
+            if !record[idx].eq_ignore_ascii_case(value) {
+                skip(format!("is a '{}', not a '{}'", &record[idx], value));
+                continue;
+            }
+        }
+
+        let password = &record[password_idx];
+        if password.is_empty() {
+            skip("has no password".to_string());
+            continue;
+        }
+
+        let url = get(url_idx);
+        let login_name = expand_template(&profile.template, |name| {
+            Some(match name {
+                "url" => url.to_string(),
+                "host" => host(url).to_string(),
+                "username" => get(username_idx).to_string(),
+                "title" => get(title_idx).to_string(),
+                "group" => get(group_idx).to_string(),
+                column => get(Some(index(column).ok()?)).to_string(),
+            })
+        });
+        let login_name = match login_name {
+            Ok(name) if name.is_empty() => {
+                skip("results in an empty login path".to_string());
+                continue;
+            }
+            Ok(name) => name,
+            Err(e) => bail!(e),
+        };
+
+        let mut login = UnencryptedLogin::new(login_name, Zeroizing::new(password.to_string()));
+        if let (Some(username_idx), false) = (username_idx, template_uses(profile, "username")) {
+            let username = record[username_idx].trim();
+            if !username.is_empty() {
+                login
+                    .extra_fields
+                    .insert("username".to_string(), username.to_string());
+            }
+        }
+        for (idx, field) in &fields {
+            let value = record[*idx].trim();
+            if value.is_empty() {
+                continue;
+            }
+
+            if *field == "otp" {
+                login
+                    .secret_fields
+                    .insert(field.to_string(), Zeroizing::new(value.to_string()));
+            } else {
+                login
+                    .extra_fields
+                    .insert(field.to_string(), value.to_string());
+            }
+        }
+
+        logins.push(login);
+    }
+
+    Ok((logins, skipped))
+}
+
+fn template_uses(profile: &CsvProfile, placeholder: &str) -> bool {
+    profile.template.contains(&format!("{{{}}}", placeholder))
+}
+
+// Walks a password-store directory and decrypts every entry with the local gpg binary.
+pub fn read_pass(dir: &Path) -> anyhow::Result<(Vec<UnencryptedLogin>, Vec<Skipped>)> {
+    if !dir.is_dir() {
+        bail!("'{}' is not a directory", dir.display());
+    }
+
+    let mut files = vec![];
+    find_gpg_files(dir, &mut files)?;
+    files.sort();
+
+    let mut logins = vec![];
+    let mut skipped = vec![];
+    for file in files {
+        let login_name = file
+            .strip_prefix(dir)?
+            .with_extension("")
+            .to_string_lossy()
+            .replace(std::path::MAIN_SEPARATOR, "/");
+
+        let result = match decrypt_gpg(&file) {
+            Ok(content) => parse_pass_entry(login_name.clone(), &content),
+            Err(e) => Err(anyhow!("could not be decrypted: {}", e)),
+        };
+        match result {
+            Ok(login) => logins.push(login),
+            Err(e) => skipped.push(Skipped {
+                source: login_name,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    Ok((logins, skipped))
+}
+
+fn find_gpg_files(dir: &Path, files: &mut Vec<std::path::PathBuf>) -> anyhow::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        // .git, .gpg-id, .extensions
+        if path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+
+        if path.is_dir() {
+            find_gpg_files(&path, files)?;
+        } else if path.extension().is_some_and(|e| e == "gpg") {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn decrypt_gpg(file: &Path) -> anyhow::Result<Zeroizing<String>> {
+    // gpg-agent asks for the passphrase through pinentry
+    let output = Command::new("gpg")
+        .args(["--quiet", "--decrypt"])
+        .arg(file)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| anyhow!("Could not run gpg: {}", e))?;
+    let stdout = Zeroizing::new(output.stdout);
+
+    if !output.status.success() {
+        bail!("gpg failed with {}", output.status);
+    }
+
+    Ok(Zeroizing::new(String::from_utf8(stdout.to_vec())?))
+}
+
+// Reads already decrypted entries as NUL-terminated pairs of login path and content, e.g. from
+// `printf '%s\0' "$name"; pass show "$name"; printf '\0'` for every entry.
+pub fn read_pass_stream(input: impl Read) -> anyhow::Result<(Vec<UnencryptedLogin>, Vec<Skipped>)> {
+    let mut reader = BufReader::new(input);
+
+    let mut logins = vec![];
+    let mut skipped = vec![];
+    loop {
+        let mut name = vec![];
+        if reader.read_until(0, &mut name)? == 0 {
+            break;
+        }
+        let mut content = Zeroizing::new(vec![]);
+        reader.read_until(0, &mut content)?;
+
+        let login_name = String::from_utf8_lossy(name.strip_suffix(&[0]).unwrap_or(&name))
+            .trim()
+            .trim_matches('/')
+            .to_string();
+        let content = content.strip_suffix(&[0]).unwrap_or(&content);
+
+        let result = std::str::from_utf8(content)
+            .map_err(|_| anyhow!("is not valid UTF-8"))
+            .and_then(|content| parse_pass_entry(login_name.clone(), content));
+        match result {
+            Ok(login) => logins.push(login),
+            Err(e) => skipped.push(Skipped {
+                source: login_name,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    Ok((logins, skipped))
+}
+
+// The first line is the password, "key: value" lines become extra fields, a bare URL the "url"
+// field, an otpauth:// URI (from pass-otp) the "otp" secret field and anything else the "notes"
+// field. Reserved field names stay in the notes.
+fn parse_pass_entry(login_name: String, content: &str) -> anyhow::Result<UnencryptedLogin> {
+    if login_name.is_empty() {
+        bail!("has an empty login path");
+    }
+
+    let mut lines = content.lines();
+    let password = lines.next().unwrap_or_default();
+    if password.is_empty() {
+        bail!("has no password on the first line");
+    }
+
+    let mut login = UnencryptedLogin::new(login_name, Zeroizing::new(password.to_string()));
+
+    let mut notes = vec![];
+    for line in lines {
+        let line = line.trim();
+        if line.starts_with("otpauth://") {
+            login
+                .secret_fields
+                .insert("otp".to_string(), Zeroizing::new(line.to_string()));
+            continue;
+        }
+        if (line.starts_with("https://") || line.starts_with("http://"))
+            && !login.extra_fields.contains_key("url")
+        {
+            login
+                .extra_fields
+                .insert("url".to_string(), line.to_string());
+            continue;
+        }
+
+        match line.split_once(": ") {
+            Some((key, value))
+                if !key.is_empty()
+                    && !key.contains(' ')
+                    && !RESERVED_FIELDS.contains(&key)
+                    && key != "notes"
+                    && !login.extra_fields.contains_key(key) =>
+            {
+                login
+                    .extra_fields
+                    .insert(key.to_string(), value.trim().to_string());
+            }
+            _ if line.is_empty() => {}
+            _ => notes.push(line),
+        }
+    }
+    if !notes.is_empty() {
+        login
+            .extra_fields
+            .insert("notes".to_string(), notes.join("\n"));
+    }
+
+    Ok(login)
+}
+
+const KDBX_SIGNATURE: [u8; 4] = [0x03, 0xd9, 0xa2, 0x9a];
+
+// Groups below the root group become path segments, the entry title the last one.
+pub fn read_keepass_xml(path: &str) -> anyhow::Result<(Vec<UnencryptedLogin>, Vec<Skipped>)> {
+    let content = Zeroizing::new(fs::read(path)?);
+    if content.starts_with(&KDBX_SIGNATURE) {
+        bail!("KDBX databases are not supported, export the database to KeePass XML first");
+    }
+
+    let doc = roxmltree::Document::parse(std::str::from_utf8(&content)?)?;
+    let root =
+        child(doc.root_element(), "Root").ok_or_else(|| anyhow!("Not a KeePass XML export"))?;
+    let recycle_bin = child(doc.root_element(), "Meta")
+        .filter(|meta| child_text(*meta, "RecycleBinEnabled") != "False")
+        .map(|meta| child_text(meta, "RecycleBinUUID"));
+
+    let mut logins = vec![];
+    let mut skipped = vec![];
+    for group in root.children().filter(|n| n.has_tag_name("Group")) {
+        read_keepass_group(group, "", recycle_bin, &mut logins, &mut skipped);
+    }
+
+    Ok((logins, skipped))
+}
+
+fn read_keepass_group(
+    group: roxmltree::Node,
+    path: &str,
+    recycle_bin: Option<&str>,
+    logins: &mut Vec<UnencryptedLogin>,
+    skipped: &mut Vec<Skipped>,
+) {
+    if recycle_bin.is_some_and(|uuid| !uuid.is_empty() && child_text(group, "UUID") == uuid) {
+        return;
+    }
+
+    for node in group.children() {
+        if node.has_tag_name("Group") {
+            let name = child_text(node, "Name").replace('/', "-");
+            let path = format!("{}/{}", path, name).trim_matches('/').to_string();
+            read_keepass_group(node, &path, recycle_bin, logins, skipped);
+        } else if node.has_tag_name("Entry") {
+            let result = read_keepass_entry(node, path, skipped);
+            match result {
+                Ok(login) => logins.push(login),
+                Err(e) => skipped.push(Skipped {
+                    source: format!("line {}", entry_line(node)),
+                    reason: e.to_string(),
+                }),
+            }
+        }
+    }
+}
+
+fn read_keepass_entry(
+    entry: roxmltree::Node,
+    path: &str,
+    skipped: &mut Vec<Skipped>,
+) -> anyhow::Result<UnencryptedLogin> {
+    let mut strings = IndexMap::new();
+    for string in entry.children().filter(|n| n.has_tag_name("String")) {
+        let value = child(string, "Value");
+        if value.and_then(|v| v.attribute("Protected")) == Some("True") {
+            bail!("contains encrypted values, export the database without protection");
+        }
+
+        let protected = value.and_then(|v| v.attribute("ProtectInMemory")) == Some("True");
+        let value = value.and_then(|v| v.text()).unwrap_or_default();
+        strings.insert(child_text(string, "Key").to_string(), (value, protected));
+    }
+    let get = |key: &str| strings.get(key).map(|(v, _)| v.trim()).unwrap_or_default();
+
+    let title = match get("Title") {
+        "" => host(get("URL")),
+        title => title,
+    }
+    .replace('/', "-");
+    if title.is_empty() {
+        bail!("has neither a title nor a URL");
+    }
+    let login_name = format!("{}/{}", path, title).trim_matches('/').to_string();
+
+    let password = get("Password");
+    if password.is_empty() {
+        bail!("has no password");
+    }
+
+    let mut login = UnencryptedLogin::new(login_name, Zeroizing::new(password.to_string()));
+
+    for (key, (value, protected)) in &strings {
+        let field = match key.as_str() {
+            "Title" | "Password" => continue,
+            "UserName" => "username",
+            "URL" => "url",
+            "Notes" => "notes",
+            // KeePassXC stores an otpauth:// URI
+            "otp" => {
+                login
+                    .secret_fields
+                    .insert("otp".to_string(), Zeroizing::new(value.to_string()));
+                continue;
+            }
+            key if key.starts_with("TimeOtp-") || key.starts_with("HmacOtp-") => {
+                skipped.push(Skipped {
+                    source: login.login_name.clone(),
+                    reason: format!("has an unsupported one-time password setting '{}'", key),
+                });
+                continue;
+            }
+            key => key,
+        };
+        if value.is_empty() {
+            continue;
+        }
+
+        if *protected {
+            login
+                .secret_fields
+                .insert(field.to_string(), Zeroizing::new(value.to_string()));
+        } else {
+            login
+                .extra_fields
+                .insert(field.to_string(), value.to_string());
+        }
+    }
+
+    for binary in entry.children().filter(|n| n.has_tag_name("Binary")) {
+        skipped.push(Skipped {
+            source: login.login_name.clone(),
+            reason: format!(
+                "has an unsupported attachment '{}'",
+                child_text(binary, "Key")
+            ),
+        });
+    }
+
+    Ok(login)
+}
+
+fn child<'a, 'i>(node: roxmltree::Node<'a, 'i>, tag: &str) -> Option<roxmltree::Node<'a, 'i>> {
+    node.children().find(|n| n.has_tag_name(tag))
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, tag: &str) -> &'a str {
+    child(node, tag).and_then(|n| n.text()).unwrap_or_default()
+}
+
+fn entry_line(node: roxmltree::Node) -> u32 {
+    node.document().text_pos_at(node.range().start).row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> UnencryptedLogin {
+        parse_pass_entry("web/github.com".to_string(), content).unwrap()
+    }
+
+    fn fields(login: &UnencryptedLogin) -> Vec<(&str, &str)> {
+        login
+            .extra_fields
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn password_and_fields() {
+        let login = parse("hunter2\nlogin: alice\nemail:  alice@example.com \n");
+
+        assert_eq!(login.login_name, "web/github.com");
+        assert_eq!(login.password.as_str(), "hunter2");
+        assert_eq!(
+            fields(&login),
+            [("login", "alice"), ("email", "alice@example.com")]
+        );
+    }
+
+    #[test]
+    fn url_lines() {
+        let login = parse("pw\nhttps://github.com/login\nhttp://example.com");
+
+        assert_eq!(
+            fields(&login),
+            [
+                ("url", "https://github.com/login"),
+                ("notes", "http://example.com")
+            ]
+        );
+    }
+
+    #[test]
+    fn url_field() {
+        let login = parse("pw\nurl: https://github.com");
+
+        assert_eq!(fields(&login), [("url", "https://github.com")]);
+    }
+
+    #[test]
+    fn otpauth_lines() {
+        let uri = "otpauth://totp/GitHub:alice?secret=JBSWY3DPEHPK3PXP&issuer=GitHub";
+        let login = parse(&format!("pw\n{}\nlogin: alice", uri));
+
+        assert_eq!(login.secret_fields["otp"].as_str(), uri);
+        assert_eq!(fields(&login), [("login", "alice")]);
+    }
+
+    #[test]
+    fn notes() {
+        let login = parse("pw\nrecovery codes below\n\nabc-def\nkey:value\nsome key: value");
+
+        assert_eq!(
+            fields(&login),
+            [(
+                "notes",
+                "recovery codes below\nabc-def\nkey:value\nsome key: value"
+            )]
+        );
+    }
+
+    #[test]
+    fn reserved_keys_stay_in_notes() {
+        let login = parse(
+            "pw\npassword: old\ncreated: yesterday\nsecret_fields: x\npassword_history: y\nnotes: n",
+        );
+
+        assert!(fields(&login).iter().all(|(k, _)| *k == "notes"));
+        assert_eq!(
+            login.extra_fields["notes"],
+            "password: old\ncreated: yesterday\nsecret_fields: x\npassword_history: y\nnotes: n"
+        );
+    }
+
+    #[test]
+    fn repeated_keys_stay_in_notes() {
+        let login = parse("pw\nlogin: alice\nlogin: bob");
+
+        assert_eq!(
+            fields(&login),
+            [("login", "alice"), ("notes", "login: bob")]
+        );
+    }
+
+    #[test]
+    fn pass_stream() {
+        let input: &[u8] = b"web/a\0pw\nlogin: alice\0/web/b/\0\nlogin: bob\0c\0\xff\0";
+        let (logins, skipped) = read_pass_stream(input).unwrap();
+
+        assert_eq!(logins[0].login_name, "web/a");
+        assert_eq!(fields(&logins[0]), [("login", "alice")]);
+        let skipped: Vec<_> = skipped
+            .iter()
+            .map(|s| (s.source.as_str(), s.reason.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                ("web/b", "has no password on the first line"),
+                ("c", "is not valid UTF-8")
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_entries() {
+        assert!(parse_pass_entry("".to_string(), "pw").is_err());
+        assert!(parse_pass_entry("a".to_string(), "").is_err());
+        assert!(parse_pass_entry("a".to_string(), "\nlogin: alice").is_err());
+    }
+
+    fn csv(content: &str, profile: &CsvProfile) -> (Vec<UnencryptedLogin>, Vec<Skipped>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logins.csv");
+        fs::write(&path, content).unwrap();
+        read_csv(path.to_str().unwrap(), profile).unwrap()
+    }
+
+    fn csv_format(content: &str, format: CsvFormat) -> Vec<UnencryptedLogin> {
+        let (logins, skipped) = csv(content, &format.profile());
+        assert!(skipped.is_empty());
+        logins
+    }
+
+    fn secrets(login: &UnencryptedLogin) -> Vec<(&str, &str)> {
+        login
+            .secret_fields
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn generic_csv() {
+        let logins = csv_format(
+            "URL,Username,Password\nhttps://github.com,alice,pw\n",
+            CsvFormat::Generic,
+        );
+
+        assert_eq!(logins[0].login_name, "https://github.com/alice");
+        assert_eq!(logins[0].password.as_str(), "pw");
+        assert!(fields(&logins[0]).is_empty());
+    }
+
+    #[test]
+    fn chrome_csv() {
+        let logins = csv_format(
+            "name,url,username,password,note\n\
+             GitHub,https://github.com/login,alice,pw,recovery codes\n",
+            CsvFormat::Chrome,
+        );
+
+        assert_eq!(logins[0].login_name, "github.com/alice");
+        assert_eq!(fields(&logins[0]), [("notes", "recovery codes")]);
+    }
+
+    #[test]
+    fn firefox_csv() {
+        let logins = csv_format(
+            "\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\"\n\
+             \"https://www.example.com:8080\",\"bob\",\"pw\",,\"\",\"{1}\"\n",
+            CsvFormat::Firefox,
+        );
+
+        assert_eq!(logins[0].login_name, "example.com/bob");
+        assert!(fields(&logins[0]).is_empty());
+    }
+
+    #[test]
+    fn bitwarden_csv() {
+        let (logins, skipped) = csv(
+            "folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp\n\
+             Work,,login,GitHub,some notes,,0,https://github.com,alice,pw,otpauth://totp/x\n\
+             ,,note,Recovery,codes,,0,,,,\n",
+            &CsvFormat::Bitwarden.profile(),
+        );
+
+        assert_eq!(logins.len(), 1);
+        assert_eq!(logins[0].login_name, "github.com/alice");
+        assert_eq!(fields(&logins[0]), [("notes", "some notes")]);
+        assert_eq!(secrets(&logins[0]), [("otp", "otpauth://totp/x")]);
+        assert_eq!(skipped[0].source, "line 3");
+        assert_eq!(skipped[0].reason, "is a 'note', not a 'login'");
+    }
+
+    #[test]
+    fn keepassxc_csv() {
+        let logins = csv_format(
+            "\"Group\",\"Title\",\"Username\",\"Password\",\"URL\",\"Notes\",\"TOTP\",\"Icon\"\n\
+             \"Root/Work\",\"GitHub\",\"alice\",\"pw\",\"https://github.com\",\"\",\"otpauth://totp/x\",\"0\"\n",
+            CsvFormat::Keepassxc,
+        );
+
+        assert_eq!(logins[0].login_name, "Root/Work/GitHub");
+        assert_eq!(
+            fields(&logins[0]),
+            [("username", "alice"), ("url", "https://github.com")]
+        );
+        assert_eq!(secrets(&logins[0]), [("otp", "otpauth://totp/x")]);
+    }
+
+    #[test]
+    fn one_password_csv() {
+        let logins = csv_format(
+            "Title,Url,Username,Password,OTPAuth,Favorite,Archived,Tags,Notes\n\
+             GitHub,https://github.com,alice,pw,otpauth://totp/x,false,false,,a note\n",
+            CsvFormat::OnePassword,
+        );
+
+        assert_eq!(logins[0].login_name, "github.com/alice");
+        assert_eq!(fields(&logins[0]), [("notes", "a note")]);
+        assert_eq!(secrets(&logins[0]), [("otp", "otpauth://totp/x")]);
+    }
+
+    #[test]
+    fn custom_csv_columns_and_template() {
+        let mut profile = CsvFormat::Custom.profile();
+        assert!(read_csv("unused.csv", &profile).is_err());
+
+        profile.password = Some("Secret".to_string());
+        profile.username = Some("User".to_string());
+        profile.fields = columns(&["Comment=notes"]);
+        profile.template = "imported/{account}/{username}".to_string();
+        let (logins, _) = csv("Account,User,Secret,Comment\nmail,alice,pw,hi\n", &profile);
+
+        assert_eq!(logins[0].login_name, "imported/mail/alice");
+        assert_eq!(fields(&logins[0]), [("notes", "hi")]);
+    }
+
+    #[test]
+    fn missing_csv_columns_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logins.csv");
+        fs::write(&path, "url,user,password\n").unwrap();
+
+        let error = read_csv(path.to_str().unwrap(), &CsvFormat::Generic.profile())
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "Missing 'username' column");
+    }
+
+    #[test]
+    fn invalid_csv_rows_are_skipped() {
+        let (logins, skipped) = csv(
+            "url,username,password\na.com,alice\nb.com,bob,\n,,pw\nc.com,carol,pw\n",
+            &CsvFormat::Generic.profile(),
+        );
+
+        assert_eq!(logins.len(), 1);
+        let skipped: Vec<_> = skipped
+            .iter()
+            .map(|s| (s.source.as_str(), s.reason.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                ("line 2", "has 2 of 3 columns"),
+                ("line 3", "has no password"),
+                ("line 4", "results in an empty login path"),
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_csv_paths_are_left_to_the_import() {
+        // the store skips the second one as a duplicate path, see api::State::import
+        let logins = csv_format(
+            "url,username,password\nhttps://a.com,alice,one\nhttp://a.com,alice,two\n",
+            CsvFormat::Firefox,
+        );
+
+        let names: Vec<_> = logins.iter().map(|l| l.login_name.as_str()).collect();
+        assert_eq!(names, ["a.com/alice", "a.com/alice"]);
+        assert_eq!(logins[1].password.as_str(), "two");
+    }
+
+    #[test]
+    fn templates() {
+        let lookup = |name: &str| match name {
+            "group" => Some("Root/Work".to_string()),
+            "title" => Some("GitHub".to_string()),
+            "empty" => Some(String::new()),
+            _ => None,
+        };
+
+        assert_eq!(
+            expand_template("{group}/{title}", lookup).unwrap(),
+            "Root/Work/GitHub"
+        );
+        assert_eq!(
+            expand_template("imported/{title}/", lookup).unwrap(),
+            "imported/GitHub"
+        );
+        assert_eq!(
+            expand_template("{empty}/{title}", lookup).unwrap(),
+            "GitHub"
+        );
+        assert!(expand_template("{nope}", lookup).is_err());
+        assert!(expand_template("{title", lookup).is_err());
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(
+            host("https://www.example.com:8080/login?x=1"),
+            "example.com"
+        );
+        assert_eq!(host("https://alice@mail.example.com"), "mail.example.com");
+        assert_eq!(host("example.com/path#top"), "example.com");
+        assert_eq!(host("android://hash@com.example.app/"), "com.example.app");
+        assert_eq!(host(""), "");
+    }
+
+    const KEEPASS_XML: &str = r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<KeePassFile>
+    <Meta>
+        <RecycleBinEnabled>True</RecycleBinEnabled>
+        <RecycleBinUUID>YmluYmluYmluYmluYmluYg==</RecycleBinUUID>
+    </Meta>
+    <Root>
+        <Group>
+            <UUID>cm9vdHJvb3Ryb290cm9vdA==</UUID>
+            <Name>Database</Name>
+            <Entry>
+                <String><Key>Title</Key><Value>Router</Value></String>
+                <String><Key>Password</Key><Value>admin</Value></String>
+            </Entry>
+            <Group>
+                <UUID>d29ya3dvcmt3b3Jrd29yaw==</UUID>
+                <Name>Work/Dev</Name>
+                <Entry>
+                    <String><Key>Title</Key><Value>GitHub</Value></String>
+                    <String><Key>UserName</Key><Value>alice</Value></String>
+                    <String><Key>Password</Key><Value ProtectInMemory="True">pw</Value></String>
+                    <String><Key>URL</Key><Value>https://github.com</Value></String>
+                    <String><Key>Notes</Key><Value></Value></String>
+                    <String><Key>Recovery</Key><Value ProtectInMemory="True">codes</Value></String>
+                    <String><Key>TimeOtp-Secret-Base32</Key><Value ProtectInMemory="True">JBSWY3DP</Value></String>
+                    <Binary><Key>id.pdf</Key><Value Ref="0"/></Binary>
+                </Entry>
+                <Entry>
+                    <String><Key>URL</Key><Value>https://www.example.com/login</Value></String>
+                    <String><Key>Password</Key><Value>pw2</Value></String>
+                    <String><Key>otp</Key><Value>otpauth://totp/x</Value></String>
+                </Entry>
+                <Entry>
+                    <String><Key>Title</Key><Value>Empty</Value></String>
+                    <String><Key>Password</Key><Value></Value></String>
+                </Entry>
+            </Group>
+            <Group>
+                <UUID>YmluYmluYmluYmluYmluYg==</UUID>
+                <Name>Recycle Bin</Name>
+                <Entry>
+                    <String><Key>Title</Key><Value>Deleted</Value></String>
+                    <String><Key>Password</Key><Value>old</Value></String>
+                </Entry>
+            </Group>
+        </Group>
+    </Root>
+</KeePassFile>
+"#;
+
+    fn keepass(content: &[u8]) -> anyhow::Result<(Vec<UnencryptedLogin>, Vec<Skipped>)> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.xml");
+        fs::write(&path, content).unwrap();
+        read_keepass_xml(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn keepass_groups_become_paths() {
+        let (logins, _) = keepass(KEEPASS_XML.as_bytes()).unwrap();
+
+        let names: Vec<_> = logins.iter().map(|l| l.login_name.as_str()).collect();
+        assert_eq!(names, ["Router", "Work-Dev/GitHub", "Work-Dev/example.com"]);
+        assert_eq!(logins[1].password.as_str(), "pw");
+        assert_eq!(secrets(&logins[2]), [("otp", "otpauth://totp/x")]);
+    }
+
+    #[test]
+    fn keepass_protected_strings_become_secret_fields() {
+        let (logins, _) = keepass(KEEPASS_XML.as_bytes()).unwrap();
+
+        assert_eq!(
+            fields(&logins[1]),
+            [("username", "alice"), ("url", "https://github.com")]
+        );
+        assert_eq!(secrets(&logins[1]), [("Recovery", "codes")]);
+    }
+
+    #[test]
+    fn keepass_skips_are_reported() {
+        let (_, skipped) = keepass(KEEPASS_XML.as_bytes()).unwrap();
+
+        let skipped: Vec<_> = skipped
+            .iter()
+            .map(|s| (s.source.as_str(), s.reason.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                (
+                    "Work-Dev/GitHub",
+                    "has an unsupported one-time password setting 'TimeOtp-Secret-Base32'"
+                ),
+                ("Work-Dev/GitHub", "has an unsupported attachment 'id.pdf'"),
+                ("line 33", "has no password"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keepass_encrypted_values_are_refused() {
+        let xml = KEEPASS_XML.replace("ProtectInMemory", "Protected");
+        let (logins, skipped) = keepass(xml.as_bytes()).unwrap();
+
+        assert_eq!(logins.len(), 2);
+        assert!(skipped[0].reason.starts_with("contains encrypted values"));
+    }
+
+    #[test]
+    fn keepass_databases_are_refused() {
+        let mut kdbx = KDBX_SIGNATURE.to_vec();
+        kdbx.extend([0x67, 0xfb, 0x4b, 0xb5]);
+
+        let error = keepass(&kdbx).err().unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("KDBX databases are not supported"));
+        assert!(keepass(b"<html></html>").is_err());
+    }
+}
@@ -1,11 +1,19 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 use directories::UserDirs;
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
+use std::{
+    ffi::OsString,
+    fs::TryLockError,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
 
 pub fn config_dir() -> anyhow::Result<PathBuf> {
     UserDirs::new()
-        .and_then(|ud| Some(ud.home_dir().to_path_buf().join(".qass")))
+        .map(|ud| ud.home_dir().to_path_buf().join(".qass"))
         .ok_or(anyhow!("Could not determine home directory"))
 }
 
@@ -25,11 +33,176 @@ where
     })
 }
 
+// writes to a sibling temp file first, so `path` always holds either the old or the new content
 pub fn save_to_file<E>(path: &PathBuf, data: &E) -> anyhow::Result<()>
+where
+    E: Serialize,
+{
+    let tmp_path = stage_file(path, "tmp", data)?;
+    fs::rename(tmp_path, path)?;
+    sync_parent(path)?;
+    Ok(())
+}
+
+// writes `data` durably to `<path>.<suffix>` and returns that path, leaving `path` untouched
+pub fn stage_file<E>(path: &Path, suffix: &str, data: &E) -> anyhow::Result<PathBuf>
 where
     E: Serialize,
 {
     let yaml = serde_yaml::to_string(data)?;
-    fs::write(path, yaml)?;
+    let staged_path = with_suffix(path, suffix);
+
+    let mut file = File::create(&staged_path)?;
+    file.write_all(yaml.as_bytes())?;
+    file.sync_all()?;
+
+    Ok(staged_path)
+}
+
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// makes renames and removals in the directory of `path` durable
+pub fn sync_parent(path: &Path) -> anyhow::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    File::open(parent)?.sync_all()?;
     Ok(())
 }
+
+pub fn remove_if_exists(path: &Path) -> anyhow::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+// Advisory lock on `<dir>/qass.lock`, released when the returned file is dropped. Waits for
+// other processes up to `timeout` before giving up. Shared locks don't need write access, and in
+// a directory that can't be written to, where the lock file can't be created, nobody can save
+// the store either, so reading it needs no lock at all.
+pub fn lock_dir(dir: &Path, exclusive: bool, timeout: Duration) -> anyhow::Result<Option<File>> {
+    let path = dir.join("qass.lock");
+    let file = if exclusive {
+        File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?
+    } else {
+        match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => match File::create_new(&path) {
+                Ok(file) => file,
+                // another process created it in the meantime
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => File::open(&path)?,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem
+                    ) =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            },
+            Err(e) => return Err(e.into()),
+        }
+    };
+
+    let start = Instant::now();
+    loop {
+        let result = if exclusive {
+            file.try_lock()
+        } else {
+            file.try_lock_shared()
+        };
+
+        match result {
+            Ok(()) => return Ok(Some(file)),
+            Err(TryLockError::WouldBlock) if start.elapsed() < timeout => {
+                thread::sleep(Duration::from_millis(100))
+            }
+            Err(TryLockError::WouldBlock) => bail!(
+                "Store at '{}' is in use by another qass process",
+                dir.display()
+            ),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_file_leaves_target_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logins.yml");
+        fs::write(&path, "old").unwrap();
+
+        let staged = stage_file(&path, "new", &["a", "b"]).unwrap();
+
+        assert_eq!(staged, dir.path().join("logins.yml.new"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(fs::read_to_string(&staged).unwrap(), "- a\n- b\n");
+    }
+
+    #[test]
+    fn save_to_file_replaces_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.yml");
+        fs::write(&path, "old").unwrap();
+
+        save_to_file(&path, &vec!["a"]).unwrap();
+
+        assert_eq!(load_from_yaml::<Vec<String>>(&path).unwrap(), ["a"]);
+        assert!(!with_suffix(&path, "tmp").exists());
+    }
+
+    #[test]
+    fn missing_and_empty_files_load_as_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("salts.yml");
+
+        assert!(load_from_yaml::<Vec<String>>(&path).unwrap().is_empty());
+        fs::write(&path, "\n").unwrap();
+        assert!(load_from_yaml::<Vec<String>>(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn remove_if_exists_ignores_missing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("save.journal");
+        fs::write(&path, "").unwrap();
+
+        remove_if_exists(&path).unwrap();
+        remove_if_exists(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn shared_and_exclusive_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let timeout = Duration::ZERO;
+
+        let reader = lock_dir(dir.path(), false, timeout).unwrap();
+        assert!(lock_dir(dir.path(), false, timeout).unwrap().is_some());
+        let error = lock_dir(dir.path(), true, timeout).unwrap_err();
+        assert!(error.to_string().contains("in use by another qass process"));
+        drop(reader);
+
+        let writer = lock_dir(dir.path(), true, timeout).unwrap();
+        assert!(lock_dir(dir.path(), false, timeout).is_err());
+        drop(writer);
+        assert!(lock_dir(dir.path(), false, timeout).is_ok());
+    }
+}
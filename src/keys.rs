@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use zeroize::Zeroizing;
+
+use crate::{
+    crypto::{derive_key, derive_subkey, generate_salt, KdfMode, KdfParams},
+    login::SaltEntry,
+};
+
+// Derives the keys for the secrets of one operation. Argon2 outputs are cached, so entries
+// encrypted in the same batch cost a single Argon2 run to decrypt.
+pub struct Keys<'a> {
+    master_password: &'a str,
+    kdf: KdfParams,
+    mode: KdfMode,
+    batch_salt: Option<String>,
+    cache: HashMap<(String, KdfParams), Zeroizing<[u8; 32]>>,
+}
+
+impl<'a> Keys<'a> {
+    pub fn new(master_password: &'a str, kdf: KdfParams, mode: KdfMode) -> Self {
+        Keys {
+            master_password,
+            kdf,
+            mode,
+            batch_salt: None,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    // returns the key for a new secret with the given salt, plus the batch salt to record with it
+    pub fn encryption_key(
+        &mut self,
+        salt: &str,
+    ) -> anyhow::Result<(Zeroizing<[u8; 32]>, Option<String>)> {
+        match self.mode {
+            KdfMode::PerEntry => Ok((
+                Zeroizing::new(derive_key(self.master_password, salt, &self.kdf)?),
+                None,
+            )),
+            KdfMode::Batch => {
+                let batch_salt = self.batch_salt.get_or_insert_with(generate_salt).clone();
+                let kdf = self.kdf.clone();
+                let key = derive_subkey(self.batch_key(&batch_salt, &kdf)?, salt)?;
+                Ok((Zeroizing::new(key), Some(batch_salt)))
+            }
+        }
+    }
+
+    pub fn decryption_key(&mut self, salt: &SaltEntry) -> anyhow::Result<Zeroizing<[u8; 32]>> {
+        match &salt.batch_salt {
+            None => Ok(Zeroizing::new(derive_key(
+                self.master_password,
+                &salt.salt,
+                &salt.kdf(),
+            )?)),
+            Some(batch_salt) => {
+                let key = derive_subkey(self.batch_key(batch_salt, &salt.kdf())?, &salt.salt)?;
+                Ok(Zeroizing::new(key))
+            }
+        }
+    }
+
+    fn batch_key(&mut self, batch_salt: &str, kdf: &KdfParams) -> anyhow::Result<&[u8; 32]> {
+        let cache_key = (batch_salt.to_string(), kdf.clone());
+        if !self.cache.contains_key(&cache_key) {
+            let key = derive_key(self.master_password, batch_salt, kdf)?;
+            self.cache.insert(cache_key.clone(), Zeroizing::new(key));
+        }
+
+        Ok(&self.cache[&cache_key])
+    }
+}
@@ -1,24 +1,295 @@
+use anyhow::bail;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
 use zeroize::Zeroizing;
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+use crate::crypto::{Cipher, KdfMode, KdfParams};
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct SaltEntry {
     pub salt: String,
     pub nonce: String,
+    // missing in entries written before format version 2, which used the defaults
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<KdfParams>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<Cipher>,
+    // set for entries encrypted in batch mode, `salt` is then the HKDF salt of the entry's subkey
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_salt: Option<String>,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub fields: IndexMap<String, SaltEntry>,
+    // salts of `LoginEntry::password_history`, at the same indices
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<SaltEntry>,
+}
+
+impl SaltEntry {
+    pub fn kdf(&self) -> KdfParams {
+        self.kdf.clone().unwrap_or_default()
+    }
+
+    pub fn cipher(&self) -> Cipher {
+        self.cipher.unwrap_or_default()
+    }
+
+    pub fn uses_kdf(&self, kdf: &KdfParams, mode: KdfMode) -> bool {
+        self.kdf() == *kdf
+            && self.batch_salt.is_some() == (mode == KdfMode::Batch)
+            && self
+                .fields
+                .values()
+                .chain(&self.history)
+                .all(|f| f.uses_kdf(kdf, mode))
+    }
+
+    // the oldest store format version that can represent this entry
+    pub fn format_version(&self) -> u32 {
+        let version = if !self.history.is_empty() {
+            4
+        } else if self.batch_salt.is_some() {
+            3
+        } else if self.kdf.is_some() || self.cipher.is_some() {
+            2
+        } else {
+            1
+        };
+
+        self.fields
+            .values()
+            .chain(&self.history)
+            .map(SaltEntry::format_version)
+            .fold(version, u32::max)
+    }
+
+    // records the implicit parameters of legacy entries, returns whether anything changed
+    pub fn make_explicit(&mut self) -> bool {
+        let mut changed = self.kdf.is_none() || self.cipher.is_none();
+        self.kdf = Some(self.kdf());
+        self.cipher = Some(self.cipher());
+
+        for field in self.fields.values_mut().chain(&mut self.history) {
+            changed |= field.make_explicit();
+        }
+
+        changed
+    }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+// field names of `LoginEntry` that extra fields can't use
+pub const RESERVED_FIELDS: [&str; 5] = [
+    "password",
+    "created",
+    "modified",
+    "password_history",
+    "secret_fields",
+];
+
+// fields of stores before format version 4, when these names were still free to use
+const LEGACY_FIELDS: [&str; 3] = ["created", "modified", "password_history"];
+
+// the name a field named like a reserved one is kept under, e.g. "created" -> "field_created"
+fn unreserved_name(key: &str, taken: impl Fn(&str) -> bool) -> String {
+    let mut name = format!("field_{}", key);
+    while taken(&name) {
+        name.insert_str(0, "field_");
+    }
+    name
+}
+
+// Renames the fields of the logins in a raw login map of a store before format version 4 that
+// would now be read as timestamps or history. Returns the paths with the old and new names.
+pub fn rename_legacy_fields(
+    node: &mut serde_yaml::Value,
+    prefix: &str,
+) -> Vec<(String, String, String)> {
+    let Some(map) = node.as_mapping_mut() else {
+        return vec![];
+    };
+
+    // a login has a password, a collection can only have a child called "password"
+    if map
+        .get("password")
+        .is_some_and(serde_yaml::Value::is_string)
+    {
+        let mut renamed = vec![];
+        for key in LEGACY_FIELDS {
+            let Some(value) = map.shift_remove(key) else {
+                continue;
+            };
+            let name = unreserved_name(key, |name| map.contains_key(name));
+            if let Some(secret_fields) = map
+                .get_mut("secret_fields")
+                .and_then(serde_yaml::Value::as_sequence_mut)
+            {
+                for field in secret_fields.iter_mut().filter(|f| *f == key) {
+                    *field = name.clone().into();
+                }
+            }
+            map.insert(name.clone().into(), value);
+            renamed.push((prefix.to_string(), key.to_string(), name));
+        }
+        return renamed;
+    }
+
+    let mut renamed = vec![];
+    for (key, child) in map.iter_mut() {
+        let Some(key) = key.as_str() else {
+            continue;
+        };
+        let path = if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{}", prefix, key)
+        };
+        renamed.extend(rename_legacy_fields(child, &path));
+    }
+    renamed
+}
+
+pub fn now() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct PreviousPassword {
+    // encrypted if the login is
+    pub password: String,
+    pub replaced: String,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct LoginEntry {
     pub password: String,
+    // RFC 3339 timestamps, missing in entries written before format version 4
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+    // newest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub password_history: Vec<PreviousPassword>,
     #[serde(flatten)]
     pub extra_fields: IndexMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secret_fields: Vec<String>,
+}
+
+impl LoginEntry {
+    pub fn is_secret(&self, key: &str) -> bool {
+        key == "password" || self.secret_fields.iter().any(|f| f == key)
+    }
+
+    // the oldest store format version that can represent this entry
+    pub fn format_version(&self) -> u32 {
+        if self.created.is_some() || self.modified.is_some() || !self.password_history.is_empty() {
+            4
+        } else {
+            1
+        }
+    }
 }
 
 pub struct UnencryptedLogin {
     pub login_name: String,
     pub password: Zeroizing<String>,
     pub extra_fields: IndexMap<String, String>,
+    pub secret_fields: IndexMap<String, Zeroizing<String>>,
+    pub created: Option<String>,
+    pub modified: Option<String>,
+    // previous passwords and when they were replaced, newest first
+    pub history: Vec<(Zeroizing<String>, String)>,
+}
+
+impl UnencryptedLogin {
+    pub fn new(login_name: String, password: Zeroizing<String>) -> Self {
+        UnencryptedLogin {
+            login_name,
+            password,
+            extra_fields: IndexMap::new(),
+            secret_fields: IndexMap::new(),
+            created: None,
+            modified: None,
+            history: vec![],
+        }
+    }
+
+    // renames fields that would clash with the keys of `LoginEntry`, as imports may contain any
+    pub fn rename_reserved_fields(&mut self) {
+        for key in RESERVED_FIELDS {
+            let taken = |name: &str| {
+                self.extra_fields.contains_key(name) || self.secret_fields.contains_key(name)
+            };
+            if self.extra_fields.contains_key(key) {
+                let name = unreserved_name(key, taken);
+                let value = self.extra_fields.shift_remove(key).unwrap_or_default();
+                self.extra_fields.insert(name, value);
+            }
+
+            let taken = |name: &str| {
+                self.extra_fields.contains_key(name) || self.secret_fields.contains_key(name)
+            };
+            if self.secret_fields.contains_key(key) {
+                let name = unreserved_name(key, taken);
+                let value = self.secret_fields.shift_remove(key).unwrap_or_default();
+                self.secret_fields.insert(name, value);
+            }
+        }
+    }
+
+    pub fn from_cleartext(login_name: String, entry: &LoginEntry) -> Self {
+        let (secret_fields, extra_fields): (IndexMap<_, _>, IndexMap<_, _>) = entry
+            .extra_fields
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .partition(|(k, _)| entry.is_secret(k));
+
+        UnencryptedLogin {
+            login_name,
+            password: Zeroizing::new(entry.password.clone()),
+            extra_fields,
+            secret_fields: secret_fields
+                .into_iter()
+                .map(|(k, v)| (k, Zeroizing::new(v)))
+                .collect(),
+            created: entry.created.clone(),
+            modified: entry.modified.clone(),
+            history: entry
+                .password_history
+                .iter()
+                .map(|p| (Zeroizing::new(p.password.clone()), p.replaced.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn into_cleartext(self) -> (String, LoginEntry) {
+        let mut extra_fields = self.extra_fields;
+        let mut secret_fields = vec![];
+        for (k, v) in self.secret_fields {
+            extra_fields.insert(k.clone(), v.to_string());
+            secret_fields.push(k);
+        }
+
+        (
+            self.login_name,
+            LoginEntry {
+                password: self.password.to_string(),
+                created: self.created,
+                modified: self.modified,
+                password_history: self
+                    .history
+                    .into_iter()
+                    .map(|(password, replaced)| PreviousPassword {
+                        password: password.to_string(),
+                        replaced,
+                    })
+                    .collect(),
+                extra_fields,
+                secret_fields,
+            },
+        )
+    }
 }
 
 #[derive(Default)]
@@ -49,7 +320,9 @@ impl Serialize for LoginMap {
     where
         S: serde::Serializer,
     {
-        NestedMap::from_entries(&self.logins).serialize(serializer)
+        NestedMap::from_entries(&self.logins)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
     }
 }
 
@@ -75,15 +348,15 @@ pub enum NestedMap {
 }
 
 impl NestedMap {
-    pub fn from_entries(entries: &IndexMap<String, LoginEntry>) -> Self {
+    pub fn from_entries(entries: &IndexMap<String, LoginEntry>) -> anyhow::Result<Self> {
         let mut root = NestedMap::Map(IndexMap::new());
 
         for (path, entry) in entries {
             let segments: Vec<&str> = path.split('/').collect();
-            insert_at_path(&mut root, &segments, entry.clone());
+            insert_at_path(&mut root, path, &segments, entry.clone())?;
         }
 
-        root
+        Ok(root)
     }
 
     pub fn extract_entries(&self, prefix: &str, map: &mut LoginMap) {
@@ -105,9 +378,14 @@ impl NestedMap {
     }
 }
 
-fn insert_at_path(node: &mut NestedMap, segments: &[&str], entry: LoginEntry) {
+fn insert_at_path(
+    node: &mut NestedMap,
+    path: &str,
+    segments: &[&str],
+    entry: LoginEntry,
+) -> anyhow::Result<()> {
     if segments.is_empty() {
-        panic!("Path must not be empty.");
+        bail!("Path must not be empty");
     }
 
     match node {
@@ -115,19 +393,28 @@ fn insert_at_path(node: &mut NestedMap, segments: &[&str], entry: LoginEntry) {
             let current = segments[0];
 
             if segments.len() == 1 {
+                if children.contains_key(current) {
+                    bail!(
+                        "Cannot insert '{}', as it is a collection, not a login",
+                        path
+                    );
+                }
                 children.insert(current.to_string(), Box::new(NestedMap::Leaf(entry)));
+            } else if let Some(child) = children.get_mut(current) {
+                insert_at_path(child, path, &segments[1..], entry)?;
             } else {
-                if let Some(mut child) = children.get_mut(current) {
-                    insert_at_path(&mut child, &segments[1..], entry);
-                } else {
-                    let mut new_child = Box::new(NestedMap::Map(IndexMap::new()));
-                    insert_at_path(&mut new_child, &segments[1..], entry);
-                    children.insert(current.to_string(), new_child);
-                }
+                let mut new_child = Box::new(NestedMap::Map(IndexMap::new()));
+                insert_at_path(&mut new_child, path, &segments[1..], entry)?;
+                children.insert(current.to_string(), new_child);
             }
+
+            Ok(())
         }
         NestedMap::Leaf(_) => {
-            panic!("Cannot insert to this path, as it is a login, not a collection. Turn it into a collection by making a new layer and naming it.")
+            bail!(
+                "Cannot insert '{}', as one of its parents is a login, not a collection",
+                path
+            )
         }
     }
 }
@@ -1,28 +1,46 @@
-use api::State;
-use clap::{Parser, Subcommand};
-use device_query::{DeviceEvents, DeviceEventsHandler, Keycode};
-use enigo::{Enigo, Keyboard, Settings};
+use api::{Backup, Side, State};
+use autotype::Action;
+use backup::Archive;
+use clap::{Args, Parser, Subcommand};
+use config::{Config, TypeConfig};
+use crypto::{KdfMode, KdfParams};
+use device_query::{DeviceEvents, DeviceEventsHandler};
+use export::ExportFormat;
+use generator::{CharClass, Generator};
+use import::{CsvFormat, CsvProfile, ImportSource, Skipped};
 use std::{
-    fs::{self, File},
-    io::Write,
+    collections::HashMap,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Once,
     },
     thread,
     time::{Duration, Instant},
 };
+use totp::Totp;
 use zeroize::Zeroizing;
 
 pub mod api;
+pub mod audit;
+pub mod autotype;
+pub mod backup;
+pub mod config;
 pub mod crypto;
+pub mod export;
+pub mod generator;
+pub mod git;
 #[cfg(feature = "gui")]
 pub mod gui;
 #[cfg(feature = "gui")]
 pub mod gui_widget;
 pub mod hidden;
+pub mod import;
 pub mod io;
+pub mod keys;
 pub mod login;
+pub mod totp;
 
 #[derive(Parser)]
 #[command(name = "qass")]
@@ -30,16 +48,46 @@ pub mod login;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Directory of the password store, falls back to $QASS_STORE, then ~/.qass
+    #[arg(long, global = true)]
+    store: Option<PathBuf>,
+    /// Name of a store defined under `profiles` in ~/.qass/config.yml
+    #[arg(long, global = true)]
+    profile: Option<String>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    Init,
+    Init {
+        /// Track every change to the store in a local git repository
+        #[arg(long)]
+        git: bool,
+    },
     Add {
         login: String,
+        #[command(flatten)]
+        generate: GenerateArgs,
+        /// Also prompt for a TOTP secret or otpauth:// URI
+        #[arg(long)]
+        otp: bool,
     },
     Type {
         login: String,
+        /// Type a single field instead of the auto-type sequence
+        #[arg(long, conflicts_with = "sequence")]
+        field: Option<String>,
+        /// Auto-type sequence, e.g. "{username}{TAB}{password}{ENTER}"
+        #[arg(long)]
+        sequence: Option<String>,
+        /// Type a previous password instead, 1 being the one before the current
+        #[arg(long, conflicts_with_all = ["field", "sequence"])]
+        previous: Option<usize>,
+    },
+    History {
+        login: String,
+    },
+    Otp {
+        login: String,
     },
     Hide {
         path: String,
@@ -49,11 +97,49 @@ enum Commands {
     },
     TypeHidden {
         login: String,
+        #[arg(long, default_value = "password")]
+        field: String,
     },
     Import {
         path: String,
+        #[arg(long, value_enum, default_value = "csv")]
+        from: ImportSource,
+        #[command(flatten)]
+        profile: ImportArgs,
+        /// Keep logins that already exist instead of replacing them
+        #[arg(long)]
+        skip_existing: bool,
+    },
+    Export {
+        #[arg(long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+        #[clap(default_value = "/")]
+        path: String,
+        /// Output file, stdout if omitted or "-"
+        out: Option<PathBuf>,
+    },
+    Audit {
+        #[clap(default_value = "/")]
+        path: String,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
     },
     List,
+    Remove {
+        path: String,
+    },
+    Mv {
+        from: String,
+        to: String,
+    },
+    Show {
+        login: String,
+    },
+    Field {
+        #[command(subcommand)]
+        command: FieldCommands,
+    },
     Sync {
         #[clap(default_value = "/")]
         path: String,
@@ -61,68 +147,406 @@ enum Commands {
     Unlock {
         path: String,
     },
+    Backup {
+        out: PathBuf,
+    },
+    Restore {
+        archive: PathBuf,
+        /// Make the store identical to the backup instead of adding what is missing
+        #[arg(long)]
+        replace: bool,
+    },
+    Merge {
+        /// Directory of the other store
+        other: PathBuf,
+        /// Copy of the store as it was when both sides were last in sync
+        #[arg(long)]
+        base: Option<PathBuf>,
+    },
+    Log {
+        #[arg(short = 'n', long, default_value_t = 20)]
+        count: usize,
+    },
+    Undo {
+        /// Commit to revert, the latest one by default
+        #[clap(default_value = "HEAD")]
+        commit: String,
+    },
+    Migrate,
+    Kdf {
+        #[command(subcommand)]
+        command: KdfCommands,
+    },
+    Rekey {
+        #[clap(default_value = "/")]
+        path: String,
+        /// Keep the master password and only re-encrypt entries that don't use the store's KDF
+        /// parameters yet
+        #[arg(long)]
+        kdf: bool,
+    },
     #[cfg(feature = "gui")]
     Gui,
 }
 
+#[derive(Subcommand)]
+enum KdfCommands {
+    Show,
+    Set {
+        /// Memory cost in KiB
+        #[arg(long)]
+        m_cost: Option<u32>,
+        /// Number of iterations
+        #[arg(long)]
+        t_cost: Option<u32>,
+        /// Degree of parallelism
+        #[arg(long)]
+        p_cost: Option<u32>,
+        /// Run Argon2 per entry, or once per operation with per-entry keys derived by HKDF
+        #[arg(long, value_enum)]
+        mode: Option<KdfMode>,
+    },
+    Calibrate {
+        /// Time a single key derivation should take on this machine
+        #[arg(long, default_value_t = 500)]
+        target_ms: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum FieldCommands {
+    Set {
+        login: String,
+        key: String,
+        #[arg(required_unless_present = "secret")]
+        value: Option<String>,
+        /// Encrypt the field, prompting for its value
+        #[arg(long, conflicts_with = "value")]
+        secret: bool,
+    },
+    Get {
+        login: String,
+        key: String,
+    },
+    Rm {
+        login: String,
+        key: String,
+    },
+}
+
+#[derive(Args)]
+struct GenerateArgs {
+    /// Generate a random password instead of prompting for one
+    #[arg(long)]
+    generate: bool,
+    #[arg(long, requires = "generate", default_value_t = 24)]
+    length: usize,
+    /// Generate a passphrase of this many words instead
+    #[arg(long, requires = "generate", conflicts_with = "charset")]
+    words: Option<usize>,
+    #[arg(long, requires = "generate", value_delimiter = ',')]
+    charset: Vec<CharClass>,
+    /// Exclude characters that are easily confused, like 'l', '1' and 'I'
+    #[arg(long, requires = "generate")]
+    no_ambiguous: bool,
+    /// Print the generated password
+    #[arg(long, requires = "generate")]
+    show: bool,
+}
+
+#[derive(Args)]
+struct ImportArgs {
+    #[arg(long, value_enum, default_value = "generic")]
+    format: CsvFormat,
+    /// Login path template, e.g. "{host}/{username}" or "{group}/{title}"
+    #[arg(long)]
+    template: Option<String>,
+    #[arg(long)]
+    url_column: Option<String>,
+    #[arg(long)]
+    username_column: Option<String>,
+    #[arg(long)]
+    password_column: Option<String>,
+    #[arg(long)]
+    title_column: Option<String>,
+    #[arg(long)]
+    group_column: Option<String>,
+    /// Import a column as an extra field, e.g. "Notes=notes"
+    #[arg(long = "field", value_name = "COLUMN=FIELD")]
+    fields: Vec<String>,
+}
+
+impl ImportArgs {
+    fn profile(self) -> anyhow::Result<CsvProfile> {
+        let mut profile = self.format.profile();
+
+        for (column, field) in [
+            (self.url_column, &mut profile.url),
+            (self.username_column, &mut profile.username),
+            (self.password_column, &mut profile.password),
+            (self.title_column, &mut profile.title),
+            (self.group_column, &mut profile.group),
+        ] {
+            if column.is_some() {
+                *field = column;
+            }
+        }
+        if let Some(template) = self.template {
+            profile.template = template;
+        }
+        for mapping in self.fields {
+            let (column, field) = mapping
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Expected COLUMN=FIELD, got '{}'", mapping))?;
+            profile.fields.push((column.to_string(), field.to_string()));
+        }
+
+        if profile.password.is_none() {
+            anyhow::bail!("The custom format requires --password-column");
+        }
+
+        Ok(profile)
+    }
+}
+
+impl GenerateArgs {
+    fn generator(&self) -> Generator {
+        match self.words {
+            Some(words) => Generator::Passphrase { words },
+            None => Generator::Characters {
+                length: self.length,
+                charset: if self.charset.is_empty() {
+                    CharClass::ALL.to_vec()
+                } else {
+                    self.charset.clone()
+                },
+                exclude_ambiguous: self.no_ambiguous,
+            },
+        }
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    let config = Config::load()?;
+    let store = config.store_dir(cli.store, cli.profile)?;
 
     match cli.command {
-        Commands::Init => init(),
-        Commands::Add { login } => add(login),
-        Commands::Type { login } => type_password(login),
-        Commands::Hide { path } => hide(path),
-        Commands::Unhide { path } => unhide(path),
-        Commands::TypeHidden { login } => type_hidden_password(login),
-        Commands::Import { path } => import_csv(path),
-        Commands::List => list_logins(),
-        Commands::Unlock { path } => unlock(path),
-        Commands::Sync { path } => sync(path),
+        Commands::Init { git } => init(&store, git),
+        Commands::Add {
+            login,
+            generate,
+            otp,
+        } => add(&store, login, generate, otp),
+        Commands::Type {
+            login,
+            field,
+            sequence,
+            previous,
+        } => match previous {
+            Some(previous) => type_previous_password(&store, login, previous, &config.typing),
+            None => type_password(&store, login, field, sequence, &config.typing),
+        },
+        Commands::History { login } => history(&store, login),
+        Commands::Otp { login } => type_otp(&store, login, &config.typing),
+        Commands::Hide { path } => hide(&store, path),
+        Commands::Unhide { path } => unhide(&store, path),
+        Commands::TypeHidden { login, field } => {
+            type_hidden_password(&store, login, field, &config.typing)
+        }
+        Commands::Import {
+            path,
+            from,
+            profile,
+            skip_existing,
+        } => import(&store, path, from, profile, skip_existing),
+        Commands::Export { format, path, out } => export(&store, format, path, out),
+        Commands::Audit { path, json } => audit(&store, path, json),
+        Commands::List => list_logins(&store),
+        Commands::Remove { path } => remove(&store, path),
+        Commands::Mv { from, to } => rename(&store, from, to),
+        Commands::Show { login } => show(&store, login),
+        Commands::Field { command } => match command {
+            FieldCommands::Set {
+                login,
+                key,
+                value: Some(value),
+                secret: false,
+            } => set_field(&store, login, key, value),
+            FieldCommands::Set { login, key, .. } => set_secret_field(&store, login, key),
+            FieldCommands::Get { login, key } => get_field(&store, login, key),
+            FieldCommands::Rm { login, key } => remove_field(&store, login, key),
+        },
+        Commands::Unlock { path } => unlock(&store, path),
+        Commands::Sync { path } => sync(&store, path),
+        Commands::Backup { out } => backup(&store, out),
+        Commands::Restore { archive, replace } => restore(&store, archive, replace),
+        Commands::Merge { other, base } => merge(&store, other, base),
+        Commands::Log { count } => log(&store, count),
+        Commands::Undo { commit } => undo(&store, commit),
+        Commands::Migrate => migrate(&store),
+        Commands::Kdf { command } => match command {
+            KdfCommands::Show => show_kdf(&store),
+            KdfCommands::Set {
+                m_cost,
+                t_cost,
+                p_cost,
+                mode,
+            } => set_kdf(&store, m_cost, t_cost, p_cost, mode),
+            KdfCommands::Calibrate { target_ms } => calibrate_kdf(&store, target_ms),
+        },
+        Commands::Rekey { path, kdf: false } => rekey(&store, path),
+        Commands::Rekey { path, kdf: true } => rekey_kdf(&store, path),
         #[cfg(feature = "gui")]
-        Commands::Gui => gui::run(),
+        Commands::Gui => gui::run(config, store),
     }
 }
 
-// TODO: move to api
-fn init() -> anyhow::Result<()> {
-    let dir = io::config_dir()?;
-    fs::create_dir_all(&dir)?;
+fn init(store: &Path, git: bool) -> anyhow::Result<()> {
+    State::init(store)?;
+    if git {
+        // holds the lock, so no save interleaves with the initial commit
+        let _state = State::load_mut(store)?;
+        git::init(store)?;
+    }
 
-    for file in &["logins.yml", "salts.yml", "hidden.yml"] {
-        let path = dir.join(file);
-        if !path.exists() {
-            File::create(path)?;
+    Ok(())
+}
+
+fn add(store: &Path, login: String, generate: GenerateArgs, otp: bool) -> anyhow::Result<()> {
+    let password = if generate.generate {
+        let password = generate.generator().generate()?;
+        if generate.show {
+            println!("Generated password: {}", *password);
         }
+        password
+    } else {
+        Zeroizing::new(rpassword::prompt_password("Password: ")?)
+    };
+    let otp_secret = if otp {
+        let secret = Zeroizing::new(rpassword::prompt_password("OTP Secret: ")?);
+        Totp::parse(&secret)?;
+        Some(secret)
+    } else {
+        None
+    };
+
+    let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
+
+    // locked only after prompting, so readers aren't blocked while waiting for input
+    let mut state = State::load_mut(store)?;
+    state.add(login.clone(), password, master_pwd.clone())?;
+    if let Some(secret) = otp_secret {
+        state.set_secret_field(&login, "otp".to_string(), secret, master_pwd)?;
     }
+    state.save(&format!("Add {}", login))
+}
 
-    Ok(())
+fn type_password(
+    store: &Path,
+    login: String,
+    field: Option<String>,
+    sequence: Option<String>,
+    config: &TypeConfig,
+) -> anyhow::Result<()> {
+    let state = State::load(store)?;
+
+    // plain fields need no master password
+    if let Some(field) = &field {
+        if field != "password" && !state.secret_fields(&login)?.contains(field) {
+            return type_password_text(state.get_field(&login, field)?, config);
+        }
+    }
+
+    let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
+
+    match field {
+        Some(field) => {
+            let password = state.get_secret(login, &field, master_pwd)?;
+            type_password_text(&password, config)
+        }
+        None => {
+            let actions = autotype::resolve(&state, &login, sequence.as_deref(), master_pwd)?;
+            type_sequence(&actions, config)
+        }
+    }
 }
 
-fn add(login: String) -> anyhow::Result<()> {
-    let mut state = State::load()?;
+fn type_previous_password(
+    store: &Path,
+    login: String,
+    previous: usize,
+    config: &TypeConfig,
+) -> anyhow::Result<()> {
+    let state = State::load(store)?;
 
-    let password = Zeroizing::new(rpassword::prompt_password("Password: ")?);
     let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
+    let password = state.get_previous(&login, previous, master_pwd)?;
 
-    state.add(login, password, master_pwd)?;
-    state.save()
+    type_password_text(&password, config)
 }
 
-fn type_password(login: String) -> anyhow::Result<()> {
-    let state = State::load()?;
+fn history(store: &Path, login: String) -> anyhow::Result<()> {
+    let state = State::load(store)?;
+
+    let (created, modified, replaced) = state.history(&login)?;
+    let unknown = "unknown".to_string();
+
+    println!("{}", login);
+    println!("  created: {}", created.unwrap_or(&unknown));
+    println!("  modified: {}", modified.unwrap_or(&unknown));
+    if replaced.is_empty() {
+        println!("  no previous passwords");
+    }
+    for (i, replaced) in replaced.iter().enumerate() {
+        println!("  previous {}: replaced {}", i + 1, replaced);
+    }
+
+    Ok(())
+}
+
+fn type_otp(store: &Path, login: String, config: &TypeConfig) -> anyhow::Result<()> {
+    let state = State::load(store)?;
 
     let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
-    let password = state.get(login, master_pwd)?;
+    let secret = state.get_secret(login, "otp", master_pwd)?;
+    let totp = Totp::parse(&secret)?;
 
-    type_password_text(&password)
+    type_otp_code(&totp, config)
 }
 
-pub fn type_password_text(password: &str) -> anyhow::Result<()> {
-    println!("Focus the target field and press CONTROL to type password (5s timeout)...");
+pub fn type_password_text(password: &str, config: &TypeConfig) -> anyhow::Result<()> {
+    if wait_for_trigger("password", config)? {
+        let actions = [Action::Text(Zeroizing::new(password.to_string()))];
+        autotype::type_actions(&actions, config)?;
+    }
+
+    Ok(())
+}
+
+pub fn type_sequence(actions: &[Action], config: &TypeConfig) -> anyhow::Result<()> {
+    if wait_for_trigger("login", config)? {
+        autotype::type_actions(actions, config)?;
+    }
+
+    Ok(())
+}
+
+pub fn type_otp_code(totp: &Totp, config: &TypeConfig) -> anyhow::Result<()> {
+    if wait_for_trigger("one-time code", config)? {
+        autotype::type_actions(&[Action::Otp(totp.clone())], config)?;
+    }
+
+    Ok(())
+}
+
+fn wait_for_trigger(target: &str, config: &TypeConfig) -> anyhow::Result<bool> {
+    let trigger_keys = config.trigger_keys()?;
+
+    println!("{}", config.prompt(target));
 
     let start_time = Instant::now();
-    let timeout = Duration::from_secs(5);
+    let timeout = Duration::from_secs(config.timeout_secs);
 
     let pressed = Arc::new(AtomicBool::new(false));
     {
@@ -131,7 +555,7 @@ pub fn type_password_text(password: &str) -> anyhow::Result<()> {
         let event_handler = DeviceEventsHandler::new(Duration::from_millis(10))
             .ok_or(anyhow::anyhow!("Could not initialize device event loop"))?;
         let _keypress_guard = event_handler.on_key_up(move |keycode| {
-            if matches!(keycode, Keycode::LControl | Keycode::RControl) {
+            if trigger_keys.contains(keycode) {
                 pressed_clone.store(true, Ordering::SeqCst);
             }
         });
@@ -145,63 +569,147 @@ pub fn type_password_text(password: &str) -> anyhow::Result<()> {
         }
     }
 
-    if pressed.load(Ordering::SeqCst) {
-        let mut enigo = Enigo::new(&Settings::default())?;
-        enigo.text(password)?;
-    }
-
-    Ok(())
+    Ok(pressed.load(Ordering::SeqCst))
 }
 
-fn hide(path: String) -> anyhow::Result<()> {
-    let mut state = State::load()?;
-
+fn hide(store: &Path, path: String) -> anyhow::Result<()> {
     let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
 
+    let mut state = State::load_mut(store)?;
     state.hide(path, master_pwd)?;
-    state.save()
+    state.save("Hide logins")
 }
 
-fn unhide(path: String) -> anyhow::Result<()> {
-    let mut state = State::load()?;
-
+fn unhide(store: &Path, path: String) -> anyhow::Result<()> {
     let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
 
+    let mut state = State::load_mut(store)?;
     state.unhide(path, master_pwd)?;
-    state.save()
+    state.save("Unhide logins")
 }
 
-fn type_hidden_password(login: String) -> anyhow::Result<()> {
-    let state = State::load()?;
+fn type_hidden_password(
+    store: &Path,
+    login: String,
+    field: String,
+    config: &TypeConfig,
+) -> anyhow::Result<()> {
+    let state = State::load(store)?;
 
     let master_pwd_unhide =
         Zeroizing::new(rpassword::prompt_password("Master Password (Unhide): ")?);
     let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
-    let password = state.get_hidden(login, master_pwd_unhide, master_pwd)?;
+    let password = state.get_hidden(login, &field, master_pwd_unhide, master_pwd)?;
 
-    type_password_text(&password)?;
+    type_password_text(&password, config)?;
 
     Ok(())
 }
 
-fn import_csv(path: String) -> anyhow::Result<()> {
-    let mut state = State::load()?;
+fn import(
+    store: &Path,
+    path: String,
+    from: ImportSource,
+    args: ImportArgs,
+    skip_existing: bool,
+) -> anyhow::Result<()> {
+    let profile = args.profile()?;
+
+    let (logins, skipped) = match from {
+        ImportSource::Csv => import::read_csv(&path, &profile)?,
+        ImportSource::Pass if path == "-" => import::read_pass_stream(std::io::stdin())?,
+        ImportSource::Pass => import::read_pass(Path::new(&path))?,
+        ImportSource::KeepassXml => import::read_keepass_xml(&path)?,
+    };
 
     let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
 
+    let mut state = State::load_mut(store)?;
     println!("Importing logins...");
 
-    // TODO: progress bar
-    let count = state.import_csv(path, master_pwd)?;
-    state.save()?;
+    let (count, skipped) = state.import(
+        logins,
+        skipped,
+        master_pwd,
+        skip_existing,
+        &mut progress_bar(),
+    )?;
+    state.save(&format!("Import {} logins", count))?;
 
     println!("Successfully imported {} logins", count);
+    print_skipped(&skipped);
 
     Ok(())
 }
 
-fn list_logins() -> anyhow::Result<()> {
-    let state = State::load()?;
+fn export(
+    store: &Path,
+    format: ExportFormat,
+    path: String,
+    out: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let state = State::load(store)?;
+    let out = out.filter(|o| o.as_os_str() != "-");
+
+    // stdout may be the export itself, so everything else goes to stderr
+    eprintln!(
+        "WARNING: This will write passwords in cleartext to {}.",
+        out.as_ref()
+            .map(|o| format!("'{}'", o.display()))
+            .unwrap_or("stdout".to_string())
+    );
+    eprintln!("Anyone with access to the output will be able to see these passwords.");
+
+    if !confirm("Are you sure you want to continue?")? {
+        eprintln!("Operation canceled.");
+        return Ok(());
+    }
+
+    let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
+
+    let (logins, failed) = state.export(&path, master_pwd, &mut progress_bar())?;
+
+    match out {
+        Some(out) => {
+            let mut options = std::fs::File::options();
+            options.write(true).create(true).truncate(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+            let mut file = options.open(&out)?;
+            // mode() only applies to new files, an existing one keeps its permissions otherwise
+            #[cfg(unix)]
+            file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+            export::write(format, &logins, &mut file)?;
+            file.sync_all()?;
+        }
+        None => export::write(format, &logins, &mut std::io::stdout().lock())?,
+    }
+
+    eprintln!("Successfully exported {} logins", logins.len());
+    if !failed.is_empty() {
+        eprintln!("Could not decrypt these entries, they were not exported:");
+        for path in failed {
+            eprintln!("  {}", path);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_skipped(skipped: &[Skipped]) {
+    if skipped.is_empty() {
+        return;
+    }
+
+    println!("Skipped {}:", skipped.len());
+    for s in skipped {
+        println!("  {}: {}", s.source, s.reason);
+    }
+}
+
+fn list_logins(store: &Path) -> anyhow::Result<()> {
+    let state = State::load(store)?;
 
     for path in state.list() {
         println!("{}", path);
@@ -210,42 +718,569 @@ fn list_logins() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn sync(path: String) -> anyhow::Result<()> {
-    let mut state = State::load()?;
+fn remove(store: &Path, path: String) -> anyhow::Result<()> {
+    let removed = State::load(store)?.remove(path.clone())?;
+
+    println!("The following logins will be deleted:");
+    for login in &removed {
+        println!("  {}", login);
+    }
+
+    if !confirm("Are you sure you want to continue?")? {
+        println!("Operation canceled.");
+        return Ok(());
+    }
+
+    let mut state = State::load_mut(store)?;
+    if state.remove(path.clone())? != removed {
+        anyhow::bail!(STORE_CHANGED);
+    }
+    state.save(&format!("Remove {}", path))?;
+
+    println!("Successfully removed {} entries", removed.len());
+
+    Ok(())
+}
+
+fn rename(store: &Path, from: String, to: String) -> anyhow::Result<()> {
+    let mut state = State::load_mut(store)?;
+
+    let moves = state.rename(from.clone(), to.clone())?;
+    state.save(&format!("Move {} to {}", from, to))?;
+
+    for (old_path, new_path) in &moves {
+        println!("{} -> {}", old_path, new_path);
+    }
+    println!("Successfully moved {} entries", moves.len());
+
+    Ok(())
+}
+
+fn show(store: &Path, login: String) -> anyhow::Result<()> {
+    let state = State::load(store)?;
+
+    println!("{}", login);
+    for (key, value) in state.fields(&login)? {
+        println!("  {}: {}", key, value);
+    }
+    for key in state.secret_fields(&login)? {
+        println!("  {}: <secret>", key);
+    }
+
+    Ok(())
+}
+
+fn set_field(store: &Path, login: String, key: String, value: String) -> anyhow::Result<()> {
+    let mut state = State::load_mut(store)?;
 
+    state.set_field(&login, key.clone(), value)?;
+    state.save(&format!("Set field {} of {}", key, login))
+}
+
+fn set_secret_field(store: &Path, login: String, key: String) -> anyhow::Result<()> {
+    let value = Zeroizing::new(rpassword::prompt_password("Value: ")?);
     let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
 
-    let count = state.sync(path, master_pwd)?;
-    state.save()?;
+    let mut state = State::load_mut(store)?;
+    state.set_secret_field(&login, key.clone(), value, master_pwd)?;
+    state.save(&format!("Set field {} of {}", key, login))
+}
 
-    println!("Successfully synced {} entries", count);
+fn get_field(store: &Path, login: String, key: String) -> anyhow::Result<()> {
+    let state = State::load(store)?;
+
+    println!("{}", state.get_field(&login, &key)?);
 
     Ok(())
 }
 
-fn unlock(path: String) -> anyhow::Result<()> {
-    let mut state = State::load()?;
+fn remove_field(store: &Path, login: String, key: String) -> anyhow::Result<()> {
+    let mut state = State::load_mut(store)?;
 
+    state.remove_field(&login, &key)?;
+    state.save(&format!("Remove field {} of {}", key, login))
+}
+
+fn sync(store: &Path, path: String) -> anyhow::Result<()> {
+    let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
+
+    let mut state = State::load_mut(store)?;
+    let count = state.sync(path, master_pwd, &mut progress_bar())?;
+    state.save(&format!("Encrypt {} entries", count))?;
+
+    println!("Successfully synced {} entries", count);
+
+    Ok(())
+}
+
+fn unlock(store: &Path, path: String) -> anyhow::Result<()> {
     println!("WARNING: This will decrypt passwords and store them in cleartext.");
     println!("Anyone with access to your store directory will be able to see these passwords.");
     println!("You can re-encrypt them later using the 'sync' command.");
-    print!("Are you sure you want to continue? [y/N]: ");
-    std::io::stdout().flush()?;
+
+    if !confirm("Are you sure you want to continue?")? {
+        println!("Operation canceled.");
+        return Ok(());
+    }
+
+    let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
+
+    let mut state = State::load_mut(store)?;
+    let count = state.unlock(path, master_pwd, &mut progress_bar())?;
+    state.save(&format!("Decrypt {} entries", count))?;
+
+    println!("Successfully unlocked {} entries", count);
+
+    Ok(())
+}
+
+fn audit(store: &Path, path: String, json: bool) -> anyhow::Result<()> {
+    let state = State::load(store)?;
+
+    let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
+    let (logins, failed) = state.audit_logins(&path, master_pwd, &mut progress_bar())?;
+    let report = audit::audit(&logins);
+
+    if json {
+        serde_json::to_writer_pretty(std::io::stdout().lock(), &report)?;
+        println!();
+    } else {
+        let width = report
+            .logins
+            .iter()
+            .map(|l| l.path.len())
+            .max()
+            .unwrap_or(0);
+        println!(
+            "{:width$}  {:>6}  {:>7}  {:9}  {:>8}  REUSED",
+            "LOGIN", "LENGTH", "ENTROPY", "STRENGTH", "AGE"
+        );
+        for login in &report.logins {
+            println!(
+                "{:width$}  {:>6}  {:>7.1}  {:9}  {:>8}  {}",
+                login.path,
+                login.length,
+                login.entropy,
+                login.strength,
+                login
+                    .age_days
+                    .map(|days| format!("{} d", days))
+                    .unwrap_or("unknown".to_string()),
+                if login.reused_by.is_empty() {
+                    ""
+                } else {
+                    "yes"
+                }
+            );
+        }
+
+        if !report.reused.is_empty() {
+            println!();
+            println!("Logins sharing the same password:");
+            for group in &report.reused {
+                println!("  {}", group.join(", "));
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        eprintln!("Could not decrypt these entries, they were not audited:");
+        for path in failed {
+            eprintln!("  {}", path);
+        }
+    }
+
+    Ok(())
+}
+
+const STORE_CHANGED: &str =
+    "The store was changed by another qass process in the meantime, run the command again";
+
+static CANCELED: AtomicBool = AtomicBool::new(false);
+static SIGINT_HANDLER: Once = Once::new();
+
+// Draws a progress bar on stderr. From the first call on, Ctrl-C cancels the running operation
+// instead of killing the process, so nothing is saved.
+fn progress_bar() -> impl FnMut(usize, usize) -> bool {
+    #[cfg(unix)]
+    SIGINT_HANDLER.call_once(|| {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+        unsafe {
+            signal_hook_registry::register(libc::SIGINT, || CANCELED.store(true, Ordering::SeqCst))
+                .ok();
+        }
+    });
+
+    const WIDTH: usize = 30;
+    let draw = std::io::stderr().is_terminal();
+
+    move |done, total| {
+        let canceled = CANCELED.load(Ordering::SeqCst);
+
+        if draw && total > 0 {
+            let filled = done * WIDTH / total;
+            eprint!(
+                "\r[{}{}] {}/{}",
+                "#".repeat(filled),
+                " ".repeat(WIDTH - filled),
+                done,
+                total
+            );
+            if done == total || canceled {
+                eprintln!();
+            }
+        }
+
+        !canceled
+    }
+}
+
+fn confirm(prompt: &str) -> anyhow::Result<bool> {
+    eprint!("{} [y/N]: ", prompt);
+    std::io::stderr().flush()?;
 
     let mut response = String::new();
     std::io::stdin().read_line(&mut response)?;
 
-    if response.trim().to_lowercase() != "y" {
+    Ok(response.trim().to_lowercase() == "y")
+}
+
+fn backup(store: &Path, out: PathBuf) -> anyhow::Result<()> {
+    let state = State::load(store)?;
+
+    let passphrase = Zeroizing::new(rpassword::prompt_password("Backup Passphrase: ")?);
+    let repeated = Zeroizing::new(rpassword::prompt_password("Repeat Backup Passphrase: ")?);
+    if passphrase != repeated {
+        anyhow::bail!("The backup passphrases don't match");
+    }
+
+    let archive = state.backup(&passphrase)?;
+    io::save_to_file(&out, &archive)?;
+
+    println!("Successfully backed up the store to '{}'", out.display());
+
+    Ok(())
+}
+
+fn restore(store: &Path, archive: PathBuf, replace: bool) -> anyhow::Result<()> {
+    let archive: Archive = serde_yaml::from_str(&std::fs::read_to_string(&archive)?)?;
+
+    let passphrase = Zeroizing::new(rpassword::prompt_password("Backup Passphrase: ")?);
+    let backup: Backup = archive.open(&passphrase)?;
+    backup.check_version()?;
+    // merged logins must stay readable with the store's master password
+    let master_pwd = if replace {
+        None
+    } else {
+        Some(Zeroizing::new(rpassword::prompt_password(
+            "Master Password: ",
+        )?))
+    };
+
+    if !store.exists() {
+        State::init(store)?;
+    }
+
+    let diff = State::load(store)?.diff_backup(&backup);
+    if diff.only_in_backup.is_empty() && diff.only_in_store.is_empty() && diff.changed.is_empty() {
+        println!("The store already matches the backup");
+        return Ok(());
+    }
+
+    for (title, paths) in [
+        ("Only in the backup", &diff.only_in_backup),
+        ("Only in the store", &diff.only_in_store),
+        ("Different in the backup", &diff.changed),
+    ] {
+        if !paths.is_empty() {
+            println!("{} ({}):", title, paths.len());
+            for path in paths {
+                println!("  {}", path);
+            }
+        }
+    }
+
+    let prompt = if replace {
+        "Replace the store with the backup? Entries only in the store will be lost"
+    } else {
+        "Add the entries only in the backup to the store?"
+    };
+    if !confirm(prompt)? {
+        println!("Operation canceled.");
+        return Ok(());
+    }
+
+    let mut state = State::load_mut(store)?;
+    if state.diff_backup(&backup) != diff {
+        anyhow::bail!(STORE_CHANGED);
+    }
+
+    let Some(master_pwd) = master_pwd else {
+        state.replace_with_backup(backup)?;
+        state.save("Replace store with backup")?;
+        println!("Successfully restored the backup");
+        return Ok(());
+    };
+
+    let (added, skipped) = state.restore(backup, master_pwd, &mut progress_bar())?;
+    if added > 0 {
+        state.save("Merge backup")?;
+    }
+
+    println!("Restored {} entries from the backup", added);
+    if !diff.changed.is_empty() {
+        println!(
+            "Kept the store's version of {} entries, use --replace to restore the backup's",
+            diff.changed.len()
+        );
+    }
+    print_skipped(&skipped);
+
+    Ok(())
+}
+
+fn merge(store: &Path, other: PathBuf, base: Option<PathBuf>) -> anyhow::Result<()> {
+    // loading the store a second time would wait for its own lock
+    let store_dir = store
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("Could not open the store at '{}': {}", store.display(), e))?;
+    for dir in std::iter::once(&other).chain(&base) {
+        if dir.canonicalize().is_ok_and(|dir| dir == store_dir) {
+            anyhow::bail!("'{}' is the store itself", dir.display());
+        }
+    }
+
+    let other_state = State::load(&other)?;
+    let base_state = base.as_deref().map(State::load).transpose()?;
+
+    // conflicts are resolved on a snapshot, then the answers are replayed under the exclusive lock
+    let mut answers: HashMap<String, Side> = HashMap::new();
+    let summary =
+        State::load(store)?.merge(&other_state, base_state.as_ref(), &mut |conflict| {
+            println!(
+                "Conflict at {}: {} here, {} in '{}'",
+                conflict.path,
+                conflict.ours,
+                conflict.theirs,
+                other.display()
+            );
+            loop {
+                print!("Keep [o]urs or take [t]heirs? ");
+                std::io::stdout().flush()?;
+
+                let mut response = String::new();
+                if std::io::stdin().read_line(&mut response)? == 0 {
+                    anyhow::bail!("Merge canceled");
+                }
+                let side = match response.trim().to_lowercase().as_str() {
+                    "o" => Side::Ours,
+                    "t" => Side::Theirs,
+                    _ => continue,
+                };
+                answers.insert(conflict.path.clone(), side);
+                return Ok(side);
+            }
+        })?;
+
+    if summary.taken.is_empty() {
+        println!("Nothing to merge");
+    } else {
+        println!("Taking over from '{}':", other.display());
+        for path in &summary.taken {
+            println!("  {}", path);
+        }
+
+        if !confirm("Save the merged store?")? {
+            println!("Operation canceled.");
+            return Ok(());
+        }
+
+        let mut state = State::load_mut(store)?;
+        let replayed = state.merge(&other_state, base_state.as_ref(), &mut |conflict| {
+            answers
+                .get(&conflict.path)
+                .copied()
+                .ok_or_else(|| anyhow::anyhow!(STORE_CHANGED))
+        })?;
+        if replayed.taken != summary.taken || replayed.collisions != summary.collisions {
+            anyhow::bail!(STORE_CHANGED);
+        }
+        state.save(&format!("Merge {}", other.display()))?;
+        println!("Successfully merged {} entries", summary.taken.len());
+    }
+
+    if !summary.collisions.is_empty() {
+        println!("These entries collide with existing paths and were not merged:");
+        for path in summary.collisions {
+            println!("  {}", path);
+        }
+    }
+
+    Ok(())
+}
+
+fn log(store: &Path, count: usize) -> anyhow::Result<()> {
+    let _state = State::load(store)?;
+    if !git::is_enabled(store) {
+        anyhow::bail!("The store has no history, enable it with 'qass init --git'");
+    }
+
+    print!("{}", git::log(store, count)?);
+
+    Ok(())
+}
+
+fn undo(store: &Path, commit: String) -> anyhow::Result<()> {
+    let state = State::load(store)?;
+    if !git::is_enabled(store) {
+        anyhow::bail!("The store has no history, enable it with 'qass init --git'");
+    }
+
+    // "HEAD" may move while the prompt is open, so the commit is pinned first
+    let commit = git::resolve(store, &commit)?;
+    let subject = git::subject(store, &commit)?;
+    drop(state);
+    if !confirm(&format!("Undo '{}'?", subject))? {
         println!("Operation canceled.");
         return Ok(());
     }
 
+    let _state = State::load_mut(store)?;
+    git::revert(store, &commit)?;
+
+    println!("Successfully undid '{}'", subject);
+
+    Ok(())
+}
+
+fn migrate(store: &Path) -> anyhow::Result<()> {
+    let mut state = State::load_mut(store)?;
+
+    let from_version = state.version();
+    let count = state.migrate();
+    if from_version == api::FORMAT_VERSION && count == 0 {
+        println!("Store is already up to date");
+        return Ok(());
+    }
+
+    state.save(&format!(
+        "Migrate store to format version {}",
+        api::FORMAT_VERSION
+    ))?;
+    println!(
+        "Migrated store from format version {} to {} ({} entries updated)",
+        from_version,
+        api::FORMAT_VERSION,
+        count
+    );
+
+    Ok(())
+}
+
+fn print_kdf(kdf: &KdfParams) {
+    println!("algorithm: {:?}", kdf.algorithm);
+    println!("m_cost: {} KiB", kdf.m_cost);
+    println!("t_cost: {}", kdf.t_cost);
+    println!("p_cost: {}", kdf.p_cost);
+}
+
+fn show_kdf(store: &Path) -> anyhow::Result<()> {
+    let state = State::load(store)?;
+    println!("mode: {:?}", state.kdf_mode());
+    print_kdf(state.kdf());
+    Ok(())
+}
+
+fn set_kdf(
+    store: &Path,
+    m_cost: Option<u32>,
+    t_cost: Option<u32>,
+    p_cost: Option<u32>,
+    mode: Option<KdfMode>,
+) -> anyhow::Result<()> {
+    let mut state = State::load_mut(store)?;
+
+    let mut kdf = state.kdf().clone();
+    kdf.m_cost = m_cost.unwrap_or(kdf.m_cost);
+    kdf.t_cost = t_cost.unwrap_or(kdf.t_cost);
+    kdf.p_cost = p_cost.unwrap_or(kdf.p_cost);
+
+    state.set_kdf(kdf)?;
+    if let Some(mode) = mode {
+        state.set_kdf_mode(mode);
+    }
+    state.save("Change key derivation parameters")?;
+
+    println!("mode: {:?}", state.kdf_mode());
+    print_kdf(state.kdf());
+    println!(
+        "New entries will use these parameters. Run 'qass rekey --kdf' to update existing ones."
+    );
+
+    Ok(())
+}
+
+fn calibrate_kdf(store: &Path, target_ms: u64) -> anyhow::Result<()> {
+    println!("Benchmarking key derivation...");
+    let (kdf, elapsed) = crypto::calibrate(Duration::from_millis(target_ms))?;
+
+    print_kdf(&kdf);
+    println!(
+        "One key derivation takes {} ms on this machine",
+        elapsed.as_millis()
+    );
+
+    if !confirm("Use these parameters for new entries?")? {
+        println!("Operation canceled.");
+        return Ok(());
+    }
+
+    let mut state = State::load_mut(store)?;
+    state.set_kdf(kdf)?;
+    state.save("Change key derivation parameters")?;
+    println!("Run 'qass rekey --kdf' to update existing entries.");
+
+    Ok(())
+}
+
+fn rekey(store: &Path, path: String) -> anyhow::Result<()> {
+    let old_master_pwd = Zeroizing::new(rpassword::prompt_password("Current Master Password: ")?);
+    let new_master_pwd = Zeroizing::new(rpassword::prompt_password("New Master Password: ")?);
+    let repeated = Zeroizing::new(rpassword::prompt_password("Repeat New Master Password: ")?);
+    if new_master_pwd != repeated {
+        anyhow::bail!("The new master passwords don't match");
+    }
+
+    let mut state = State::load_mut(store)?;
+    let (count, failed) = state.rekey(path, old_master_pwd, new_master_pwd, &mut progress_bar())?;
+    state.save(&format!("Change master password of {} entries", count))?;
+
+    print_rekey_result(count, &failed);
+
+    Ok(())
+}
+
+fn rekey_kdf(store: &Path, path: String) -> anyhow::Result<()> {
     let master_pwd = Zeroizing::new(rpassword::prompt_password("Master Password: ")?);
 
-    let count = state.unlock(path, master_pwd);
-    state.save()?;
+    let mut state = State::load_mut(store)?;
+    let (count, failed) = state.rekey_kdf(path, master_pwd, &mut progress_bar())?;
+    state.save(&format!(
+        "Re-encrypt {} entries with new key derivation parameters",
+        count
+    ))?;
 
-    println!("Successfully unlocked {} entries", count);
+    print_rekey_result(count, &failed);
 
     Ok(())
 }
+
+fn print_rekey_result(count: usize, failed: &[String]) {
+    println!("Successfully re-encrypted {} entries", count);
+    if !failed.is_empty() {
+        println!("Could not decrypt these entries and hidden paths, they were left unchanged:");
+        for path in failed {
+            println!("  {}", path);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use zeroize::Zeroizing;
+
+#[derive(Clone, Copy)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+#[derive(Clone)]
+pub struct Totp {
+    secret: Zeroizing<Vec<u8>>,
+    algorithm: Algorithm,
+    digits: u32,
+    period: u64,
+}
+
+impl Totp {
+    pub fn new(secret: Vec<u8>, algorithm: Algorithm, digits: u32, period: u64) -> Self {
+        Totp {
+            secret: Zeroizing::new(secret),
+            algorithm,
+            digits,
+            period,
+        }
+    }
+
+    // accepts either a bare base32 secret or an otpauth://totp/ URI
+    pub fn parse(secret: &str) -> anyhow::Result<Totp> {
+        let secret = secret.trim();
+
+        let Some(uri) = secret.strip_prefix("otpauth://") else {
+            return Ok(Totp::new(base32_decode(secret)?, Algorithm::Sha1, 6, 30));
+        };
+
+        if !uri.starts_with("totp/") {
+            bail!("Only TOTP URIs are supported");
+        }
+
+        let query = uri.split_once('?').map(|(_, q)| q).unwrap_or_default();
+
+        let mut totp = Totp::new(vec![], Algorithm::Sha1, 6, 30);
+        for (key, value) in query.split('&').filter_map(|p| p.split_once('=')) {
+            match key {
+                "secret" => totp.secret = Zeroizing::new(base32_decode(value)?),
+                "algorithm" => {
+                    totp.algorithm = match value.to_uppercase().as_str() {
+                        "SHA1" => Algorithm::Sha1,
+                        "SHA256" => Algorithm::Sha256,
+                        "SHA512" => Algorithm::Sha512,
+                        _ => bail!("Unsupported TOTP algorithm '{}'", value),
+                    }
+                }
+                "digits" => totp.digits = value.parse()?,
+                "period" => totp.period = value.parse()?,
+                _ => {}
+            }
+        }
+
+        if totp.secret.is_empty() {
+            bail!("TOTP URI does not contain a secret");
+        }
+        if !(6..=9).contains(&totp.digits) {
+            bail!("TOTP codes must have between 6 and 9 digits");
+        }
+        if totp.period == 0 {
+            bail!("TOTP period must not be zero");
+        }
+
+        Ok(totp)
+    }
+
+    pub fn generate(&self, unix_time: u64) -> anyhow::Result<Zeroizing<String>> {
+        let counter = (unix_time / self.period).to_be_bytes();
+
+        let digest = Zeroizing::new(match self.algorithm {
+            Algorithm::Sha1 => hmac::<Hmac<Sha1>>(&self.secret, &counter)?,
+            Algorithm::Sha256 => hmac::<Hmac<Sha256>>(&self.secret, &counter)?,
+            Algorithm::Sha512 => hmac::<Hmac<Sha512>>(&self.secret, &counter)?,
+        });
+
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        let code = binary as u64 % 10u64.pow(self.digits);
+
+        Ok(Zeroizing::new(format!(
+            "{:0width$}",
+            code,
+            width = self.digits as usize
+        )))
+    }
+
+    pub fn now(&self) -> anyhow::Result<Zeroizing<String>> {
+        let unix_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.generate(unix_time)
+    }
+}
+
+fn hmac<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut mac = <M as Mac>::new_from_slice(key).map_err(|_| anyhow!("Invalid TOTP secret"))?;
+    mac.update(message);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+fn base32_decode(encoded: &str) -> anyhow::Result<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut bytes = vec![];
+    let mut buffer = 0u64;
+    let mut bits = 0;
+
+    for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase() as u8)
+            .ok_or_else(|| anyhow!("TOTP secret is not valid base32"))?;
+
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    if bytes.is_empty() {
+        bail!("TOTP secret must not be empty");
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1_KEY: &[u8] = b"12345678901234567890";
+    const SHA256_KEY: &[u8] = b"12345678901234567890123456789012";
+    const SHA512_KEY: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    // RFC 4226, Appendix D: HOTP is TOTP with a period of one "second" per counter value
+    #[test]
+    fn hotp_test_vectors() {
+        let hotp = Totp::new(SHA1_KEY.to_vec(), Algorithm::Sha1, 6, 1);
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(hotp.generate(counter as u64).unwrap().as_str(), *code);
+        }
+    }
+
+    // RFC 6238, Appendix B
+    #[test]
+    fn totp_test_vectors() {
+        let vectors = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+
+        let sha1 = Totp::new(SHA1_KEY.to_vec(), Algorithm::Sha1, 8, 30);
+        let sha256 = Totp::new(SHA256_KEY.to_vec(), Algorithm::Sha256, 8, 30);
+        let sha512 = Totp::new(SHA512_KEY.to_vec(), Algorithm::Sha512, 8, 30);
+        for (time, code1, code256, code512) in vectors {
+            assert_eq!(sha1.generate(time).unwrap().as_str(), code1);
+            assert_eq!(sha256.generate(time).unwrap().as_str(), code256);
+            assert_eq!(sha512.generate(time).unwrap().as_str(), code512);
+        }
+    }
+
+    #[test]
+    fn parse_uri() {
+        // base32 of SHA1_KEY
+        let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+        let default = Totp::parse(&format!("otpauth://totp/x?secret={}", secret)).unwrap();
+        assert_eq!(default.generate(59).unwrap().as_str(), "287082");
+
+        let uri = format!(
+            "otpauth://totp/Example:alice?secret={}&issuer=Example&algorithm=SHA1&digits=8&period=30",
+            secret
+        );
+        assert_eq!(
+            Totp::parse(&uri).unwrap().generate(59).unwrap().as_str(),
+            "94287082"
+        );
+
+        assert!(Totp::parse("otpauth://hotp/x?secret=GEZDGNBV").is_err());
+        assert!(Totp::parse("otpauth://totp/x?issuer=Example").is_err());
+        assert!(Totp::parse("otpauth://totp/x?secret=GEZDGNBV&digits=5").is_err());
+        assert!(Totp::parse("otpauth://totp/x?secret=GEZDGNBV&period=0").is_err());
+        assert!(Totp::parse("otpauth://totp/x?secret=GEZDGNBV&algorithm=MD5").is_err());
+    }
+
+    #[test]
+    fn base32_padding() {
+        assert_eq!(base32_decode("MY======").unwrap(), b"f");
+        assert_eq!(base32_decode("MZXQ====").unwrap(), b"fo");
+        assert_eq!(base32_decode("MZXW6===").unwrap(), b"foo");
+        assert_eq!(base32_decode("MZXW6YQ=").unwrap(), b"foob");
+        assert_eq!(base32_decode("MZXW6YTB").unwrap(), b"fooba");
+        assert_eq!(base32_decode("MZXW6YTBOI======").unwrap(), b"foobar");
+        // padding is optional
+        assert_eq!(base32_decode("MZXW6YQ").unwrap(), b"foob");
+    }
+
+    #[test]
+    fn base32_lowercase_and_whitespace() {
+        assert_eq!(base32_decode("mzxw6ytboi").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw 6ytb oi").unwrap(), b"foobar");
+        assert_eq!(
+            base32_decode("JBSWY3DPEHPK3PXP").unwrap(),
+            base32_decode("jbsw y3dp ehpk 3pxp").unwrap()
+        );
+    }
+
+    #[test]
+    fn base32_invalid() {
+        assert!(base32_decode("").is_err());
+        assert!(base32_decode("====").is_err());
+        assert!(base32_decode("MZXW1").is_err());
+        assert!(base32_decode("MZXW8").is_err());
+        assert!(base32_decode("MZ-XW").is_err());
+        assert!(Totp::parse("not base32!").is_err());
+    }
+}